@@ -0,0 +1,277 @@
+use crate::{
+    datagrams::UdpDatagrams,
+    frame::UdpFramed,
+    framed_connected::UdpFramedConnected,
+    framed_connected_recv::UdpFramedConnectedRecv,
+    framed_connected_send::UdpFramedConnectedSend,
+    framed_impl::{
+        RWFrames, ReadFrame, WriteFrame, DEFAULT_HIGH_WATER_MARK, DEFAULT_SEND_MAX_DATAGRAM_SIZE,
+        INITIAL_RD_CAPACITY, INITIAL_WR_CAPACITY,
+    },
+    framed_recv::UdpFramedRecv,
+    framed_send::UdpFramedSend,
+    trailing::TrailingData,
+};
+
+use tokio::net::UdpSocket;
+
+use bytes::BytesMut;
+use std::{borrow::Borrow, io, mem};
+
+/// Configure buffers and options before creating a [`UdpFramed`], [`UdpFramedRecv`],
+/// [`UdpFramedSend`], [`UdpDatagrams`] or one of the connected counterparts.
+///
+/// Options that don't apply to the type being built are ignored, e.g. the write
+/// capacity when building a `UdpFramedRecv`.
+///
+/// ```
+/// # use tokio::net::UdpSocket;
+/// # use tokio_util::codec::LinesCodec;
+/// use tokio_udp_framed::{TrailingData, UdpFramedBuilder};
+///
+/// # #[tokio::main]
+/// # async fn main() -> std::io::Result<()> {
+/// let socket = UdpSocket::bind("127.0.0.1:0").await?;
+/// let framed = UdpFramedBuilder::new()
+///     .recv_max_datagram_size(1500)
+///     .send_high_water_mark(16)
+///     .trailing_data(TrailingData::Error)
+///     .build(socket, LinesCodec::new());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct UdpFramedBuilder {
+    read_capacity: Option<usize>,
+    write_capacity: usize,
+    read_buffer: Option<BytesMut>,
+    write_buffer: Option<BytesMut>,
+    recv_max_datagram_size: usize,
+    high_water_mark: usize,
+    send_max_datagram_size: usize,
+    trailing: TrailingData,
+    zero_copy: bool,
+    ignore_icmp_errors: bool,
+}
+
+impl Default for UdpFramedBuilder {
+    fn default() -> Self {
+        Self {
+            read_capacity: None,
+            write_capacity: INITIAL_WR_CAPACITY,
+            read_buffer: None,
+            write_buffer: None,
+            recv_max_datagram_size: INITIAL_RD_CAPACITY,
+            high_water_mark: DEFAULT_HIGH_WATER_MARK,
+            send_max_datagram_size: DEFAULT_SEND_MAX_DATAGRAM_SIZE,
+            trailing: TrailingData::default(),
+            zero_copy: false,
+            ignore_icmp_errors: false,
+        }
+    }
+}
+
+impl UdpFramedBuilder {
+    /// Create a builder with the same defaults `new` uses on the framed types.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the capacity the read buffer is allocated with.
+    ///
+    /// By default it is grown to the receive max datagram size on the first read. In
+    /// zero-copy mode the buffers it is refilled with are allocated with this capacity
+    /// too, or room for one datagram if that is larger, instead of room for four
+    /// datagrams.
+    pub fn read_capacity(mut self, capacity: usize) -> Self {
+        self.read_capacity = Some(capacity);
+        self
+    }
+
+    /// Sets the initial capacity of the buffer frames are encoded into, 8KiB by default.
+    pub fn write_capacity(mut self, capacity: usize) -> Self {
+        self.write_capacity = capacity;
+        self
+    }
+
+    /// Use a pre-allocated buffer for reads, any contents are cleared.
+    ///
+    /// It is grown to the read capacity if it is smaller.
+    pub fn read_buffer(mut self, buffer: BytesMut) -> Self {
+        self.read_buffer = Some(buffer);
+        self
+    }
+
+    /// Use a pre-allocated buffer to encode frames into, any contents are cleared.
+    ///
+    /// It is grown to the write capacity if it is smaller.
+    pub fn write_buffer(mut self, buffer: BytesMut) -> Self {
+        self.write_buffer = Some(buffer);
+        self
+    }
+
+    /// Sets the most bytes that will be received for a single datagram, 64KiB by default.
+    /// A size of 0 is raised to 1.
+    ///
+    /// See [`UdpFramedRecv::set_recv_max_datagram_size`].
+    pub fn recv_max_datagram_size(mut self, max_datagram_size: usize) -> Self {
+        self.recv_max_datagram_size = max_datagram_size;
+        self
+    }
+
+    /// Sets the number of datagrams that may be queued for sending before `poll_ready`
+    /// waits for them to be flushed.
+    ///
+    /// See [`UdpFramedSend::set_send_high_water_mark`].
+    pub fn send_high_water_mark(mut self, hwm: usize) -> Self {
+        self.high_water_mark = hwm;
+        self
+    }
+
+    /// Sets the most bytes allowed in a single encoded datagram, 65507 by default.
+    ///
+    /// See [`UdpFramedSend::set_send_max_datagram_size`].
+    pub fn send_max_datagram_size(mut self, max_datagram_size: usize) -> Self {
+        self.send_max_datagram_size = max_datagram_size;
+        self
+    }
+
+    /// Sets what to do with bytes the decoder leaves behind in a datagram.
+    pub fn trailing_data(mut self, policy: TrailingData) -> Self {
+        self.trailing = policy;
+        self
+    }
+
+    /// Split each received datagram off into its own buffer before decoding it.
+    ///
+    /// See [`UdpFramedRecv::set_recv_zero_copy`].
+    pub fn recv_zero_copy(mut self, enabled: bool) -> Self {
+        self.zero_copy = enabled;
+        self
+    }
+
+    /// Skip receive errors caused by ICMP messages about earlier sends.
+    ///
+    /// This is the only way to set it for the connected types. See
+    /// [`UdpFramedRecv::set_recv_ignore_icmp_errors`].
+    pub fn recv_ignore_icmp_errors(mut self, enabled: bool) -> Self {
+        self.ignore_icmp_errors = enabled;
+        self
+    }
+
+    /// Create a `UdpFramed` from the socket and codec.
+    pub fn build<T, C>(mut self, socket: T, codec: C) -> UdpFramed<T, C>
+    where
+        T: Borrow<UdpSocket>,
+    {
+        let state = RWFrames {
+            read: self.read_frame(),
+            write: self.write_frame(),
+        };
+        UdpFramed::from_parts(socket, codec, state)
+    }
+
+    /// Create a `UdpFramedRecv` from the socket and codec.
+    pub fn build_recv<T, C>(mut self, socket: T, codec: C) -> UdpFramedRecv<T, C>
+    where
+        T: Borrow<UdpSocket>,
+    {
+        UdpFramedRecv::from_parts(socket, codec, self.read_frame())
+    }
+
+    /// Create a `UdpFramedSend` from the socket and codec.
+    pub fn build_send<T, C>(mut self, socket: T, codec: C) -> UdpFramedSend<T, C>
+    where
+        T: Borrow<UdpSocket>,
+    {
+        UdpFramedSend::from_parts(socket, codec, self.write_frame())
+    }
+
+    /// Create a `UdpDatagrams` from the socket.
+    pub fn build_datagrams<T>(mut self, socket: T) -> UdpDatagrams<T>
+    where
+        T: Borrow<UdpSocket>,
+    {
+        UdpDatagrams::from_parts(socket, self.read_frame())
+    }
+
+    /// Create a `UdpFramedConnected` from a connected socket and codec.
+    ///
+    /// Returns an error if the socket hasn't been connected.
+    pub fn build_connected<T, C>(
+        mut self,
+        socket: T,
+        codec: C,
+    ) -> io::Result<UdpFramedConnected<T, C>>
+    where
+        T: Borrow<UdpSocket>,
+    {
+        let state = RWFrames {
+            read: self.read_frame(),
+            write: self.write_frame(),
+        };
+        UdpFramedConnected::from_parts(socket, codec, state)
+    }
+
+    /// Create a `UdpFramedConnectedRecv` from a connected socket and codec.
+    ///
+    /// Returns an error if the socket hasn't been connected.
+    pub fn build_connected_recv<T, C>(
+        mut self,
+        socket: T,
+        codec: C,
+    ) -> io::Result<UdpFramedConnectedRecv<T, C>>
+    where
+        T: Borrow<UdpSocket>,
+    {
+        UdpFramedConnectedRecv::from_parts(socket, codec, self.read_frame())
+    }
+
+    /// Create a `UdpFramedConnectedSend` from a connected socket and codec.
+    ///
+    /// Returns an error if the socket hasn't been connected.
+    pub fn build_connected_send<T, C>(
+        mut self,
+        socket: T,
+        codec: C,
+    ) -> io::Result<UdpFramedConnectedSend<T, C>>
+    where
+        T: Borrow<UdpSocket>,
+    {
+        UdpFramedConnectedSend::from_parts(socket, codec, self.write_frame())
+    }
+
+    fn read_frame(&mut self) -> ReadFrame {
+        let mut frame = match (self.read_buffer.take(), self.read_capacity) {
+            (Some(buffer), _) => ReadFrame::from(buffer),
+            (None, Some(capacity)) => ReadFrame {
+                buffer: BytesMut::with_capacity(capacity),
+                ..ReadFrame::default()
+            },
+            (None, None) => ReadFrame::default(),
+        };
+        if let Some(capacity) = self.read_capacity {
+            frame.buffer.reserve(capacity);
+        }
+        frame.capacity = self.read_capacity;
+        frame.trailing = mem::take(&mut self.trailing);
+        frame.set_max_datagram_size(self.recv_max_datagram_size);
+        frame.set_zero_copy(self.zero_copy);
+        frame.ignore_icmp_errors = self.ignore_icmp_errors;
+        frame
+    }
+
+    fn write_frame(&mut self) -> WriteFrame {
+        let mut frame = match self.write_buffer.take() {
+            Some(buffer) => WriteFrame::from(buffer),
+            None => WriteFrame {
+                buffer: BytesMut::with_capacity(self.write_capacity),
+                ..WriteFrame::default()
+            },
+        };
+        frame.buffer.reserve(self.write_capacity);
+        frame.high_water_mark = self.high_water_mark;
+        frame.max_datagram_size = self.send_max_datagram_size;
+        frame
+    }
+}
@@ -0,0 +1,324 @@
+use crate::{
+    codec::{DatagramDecoder, DatagramEncoder, Resilient},
+    framed_recv::UdpFramedRecv,
+    framed_send::UdpFramedSend,
+};
+
+use tokio::{
+    net::UdpSocket,
+    sync::{oneshot, Mutex},
+    task::JoinHandle,
+    time,
+};
+use tokio_stream::StreamExt;
+
+use futures_sink::Sink;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt,
+    future::poll_fn,
+    hash::Hash,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        self,
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_RETRIES: usize = 2;
+
+/// A codec whose requests and responses carry a correlation id, so a [`UdpClient`]
+/// can match each response to the request it answers.
+pub trait Correlate: DatagramDecoder {
+    /// The type of requests, encoded with `DatagramEncoder<Self::Request>`.
+    type Request;
+    /// The id shared by a request and its response.
+    type Id: Eq + Hash + Clone;
+
+    /// Assign a fresh id to `request` and return it.
+    ///
+    /// This is called once per request, retransmissions reuse the id. Ids only need
+    /// to be unique among the requests still waiting for a response, a request whose
+    /// id is already in use fails with an `io::Error` of kind `AlreadyExists`.
+    fn assign_id(&mut self, request: &mut Self::Request) -> Self::Id;
+
+    /// Returns the id of `response`, or `None` if it doesn't carry one.
+    fn response_id(&self, response: &Self::Item) -> Option<Self::Id>;
+}
+
+// each entry carries a token unique to the request, so a request only ever forgets its
+// own entry and not a later one that reused the id
+type Waiting<C> = (
+    SocketAddr,
+    u64,
+    oneshot::Sender<<C as DatagramDecoder>::Item>,
+);
+type Pending<C> = sync::Mutex<Option<HashMap<<C as Correlate>::Id, Waiting<C>>>>;
+
+/// Sends requests on a shared socket and resolves each one with the response that
+/// carries its correlation id.
+///
+/// A `UdpClient` splits the socket into a [`UdpFramedSend`] and a [`UdpFramedRecv`].
+/// Responses are received by a task spawned on creation and handed to the request
+/// with the same [`Correlate::Id`], as long as they come from the address the request
+/// was sent to. Anything else, including datagrams the codec fails to decode and
+/// responses that arrive too late, is dropped.
+///
+/// A request that gets no response within the timeout is sent again with the timeout
+/// doubled, up to the configured number of retries, after which it fails with an
+/// `io::Error` of kind `TimedOut`. Dropping a request future abandons it.
+///
+/// If receiving fails with anything other than an ICMP-induced error the task stops
+/// and every request fails from then on.
+pub struct UdpClient<C>
+where
+    C: Correlate,
+{
+    socket: Arc<UdpSocket>,
+    send: Mutex<UdpFramedSend<Arc<UdpSocket>, C>>,
+    pending: Arc<Pending<C>>,
+    next_token: AtomicU64,
+    timeout: Duration,
+    retries: usize,
+    recv_task: JoinHandle<()>,
+}
+
+impl<C> UdpClient<C>
+where
+    C: Correlate + Clone + Send + 'static,
+    C::Item: Send,
+    C::Id: Send,
+{
+    /// Create a new `UdpClient` on `socket`, sending and receiving with clones of
+    /// `codec`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    pub fn new(socket: Arc<UdpSocket>, codec: C) -> UdpClient<C> {
+        let mut recv = UdpFramedRecv::new(
+            socket.clone(),
+            Resilient::new(codec.clone(), |_: C::Error, _| {}),
+        );
+        recv.set_recv_ignore_icmp_errors(true);
+
+        let pending: Arc<Pending<C>> = Arc::new(sync::Mutex::new(Some(HashMap::new())));
+        let recv_task = tokio::spawn({
+            let pending = pending.clone();
+            async move {
+                while let Some(Ok((response, addr))) = recv.next().await {
+                    let id = match recv.codec().get_ref().response_id(&response) {
+                        Some(id) => id,
+                        None => continue,
+                    };
+                    let mut pending = pending.lock().unwrap();
+                    let waiting = pending.as_mut().expect("only taken by this task");
+                    if waiting.get(&id).is_some_and(|(to, _, _)| *to == addr) {
+                        let (_, _, tx) = waiting.remove(&id).unwrap();
+                        let _ = tx.send(response);
+                    }
+                }
+                // dropping the senders fails every request still waiting
+                pending.lock().unwrap().take();
+            }
+        });
+
+        Self {
+            send: Mutex::new(UdpFramedSend::new(socket.clone(), codec)),
+            socket,
+            pending,
+            next_token: AtomicU64::new(0),
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            recv_task,
+        }
+    }
+}
+
+impl<C> UdpClient<C>
+where
+    C: Correlate,
+{
+    /// Returns a reference to the underlying socket.
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Returns how long to wait for the response to the first attempt of a request.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Wait `timeout` for the response to the first attempt of a request, doubling it
+    /// for every retry. The default is 1 second.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns how many times a request is sent again before giving up.
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+
+    /// Send a request up to `retries` more times when no response arrives in time. The
+    /// default is 2.
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    /// Returns the number of requests waiting for a response.
+    pub fn pending(&self) -> usize {
+        self.pending
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, HashMap::len)
+    }
+
+    /// Send `request` to `addr` and wait for the response to it.
+    ///
+    /// See the struct level documentation for how responses are matched and requests
+    /// retried.
+    pub async fn request(
+        &self,
+        request: C::Request,
+        addr: SocketAddr,
+    ) -> Result<C::Item, <C as DatagramEncoder<C::Request>>::Error>
+    where
+        C: DatagramEncoder<C::Request>,
+        C::Request: Clone,
+    {
+        self.request_with_timeout(request, addr, self.timeout).await
+    }
+
+    /// Send `request` to `addr` and wait for the response to it, waiting `timeout`
+    /// for the first attempt instead of the client's timeout.
+    pub async fn request_with_timeout(
+        &self,
+        mut request: C::Request,
+        addr: SocketAddr,
+        mut timeout: Duration,
+    ) -> Result<C::Item, <C as DatagramEncoder<C::Request>>::Error>
+    where
+        C: DatagramEncoder<C::Request>,
+        C::Request: Clone,
+    {
+        let (tx, mut rx) = oneshot::channel();
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        let id = self.send.lock().await.encoder_mut().assign_id(&mut request);
+        match self.pending.lock().unwrap().as_mut() {
+            Some(waiting) => match waiting.entry(id.clone()) {
+                Entry::Vacant(entry) => entry.insert((addr, token, tx)),
+                // the request already waiting keeps the id
+                Entry::Occupied(_) => return Err(id_in_use().into()),
+            },
+            None => return Err(closed().into()),
+        };
+        let _abandon = Abandon::<C> {
+            pending: &self.pending,
+            id,
+            token,
+        };
+
+        for _ in 0..=self.retries {
+            self.send_one(request.clone(), addr).await?;
+            match time::timeout(timeout, &mut rx).await {
+                Ok(Ok(response)) => return Ok(response),
+                Ok(Err(_)) => return Err(closed().into()),
+                Err(_) => timeout *= 2,
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "no response from {} after {} attempts",
+                addr,
+                self.retries + 1
+            ),
+        )
+        .into())
+    }
+
+    async fn send_one(
+        &self,
+        request: C::Request,
+        addr: SocketAddr,
+    ) -> Result<(), <C as DatagramEncoder<C::Request>>::Error>
+    where
+        C: DatagramEncoder<C::Request>,
+    {
+        let mut send = self.send.lock().await;
+        let mut send = Pin::new(&mut *send);
+        poll_fn(|cx| Sink::<(C::Request, SocketAddr)>::poll_ready(send.as_mut(), cx)).await?;
+        send.as_mut().start_send((request, addr))?;
+        poll_fn(|cx| Sink::<(C::Request, SocketAddr)>::poll_flush(send.as_mut(), cx)).await
+    }
+}
+
+impl<C> Drop for UdpClient<C>
+where
+    C: Correlate,
+{
+    fn drop(&mut self) {
+        self.recv_task.abort();
+    }
+}
+
+impl<C> fmt::Debug for UdpClient<C>
+where
+    C: Correlate,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpClient")
+            .field("io", self.get_ref())
+            .field("pending", &self.pending())
+            .field("timeout", &self.timeout)
+            .field("retries", &self.retries)
+            .finish()
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "the client stopped receiving responses after a socket error",
+    )
+}
+
+fn id_in_use() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "the id is already in use by a request waiting for a response",
+    )
+}
+
+/// Forgets a request once it is answered, has failed or its future is dropped.
+struct Abandon<'a, C>
+where
+    C: Correlate,
+{
+    pending: &'a Pending<C>,
+    id: C::Id,
+    token: u64,
+}
+
+impl<C> Drop for Abandon<'_, C>
+where
+    C: Correlate,
+{
+    fn drop(&mut self) {
+        if let Some(waiting) = self.pending.lock().unwrap().as_mut() {
+            if waiting
+                .get(&self.id)
+                .is_some_and(|(_, token, _)| *token == self.token)
+            {
+                waiting.remove(&self.id);
+            }
+        }
+    }
+}
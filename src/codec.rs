@@ -0,0 +1,246 @@
+use crate::{error::UdpFramedError, meta::RecvMeta};
+
+use tokio_util::codec::{Decoder, Encoder};
+
+use bytes::BytesMut;
+use std::{fmt, io, net::SocketAddr};
+
+/// Decodes frames from whole datagrams.
+///
+/// Unlike `Decoder`, which works on a byte stream, `decode_datagram` is always handed
+/// exactly one complete datagram together with its [`RecvMeta`]. It is called until it
+/// returns `Ok(None)`, so a datagram can hold zero or more frames. Whatever is left in
+/// `datagram` at that point is handled by the framed type's [`TrailingData`] policy,
+/// the next call is for the next datagram.
+///
+/// Every `Decoder` is a `DatagramDecoder` that decodes with `decode_eof`, so existing
+/// codecs work as they are.
+///
+/// [`TrailingData`]: crate::TrailingData
+pub trait DatagramDecoder {
+    /// The type of decoded frames.
+    type Item;
+
+    /// The type of unrecoverable decode errors.
+    ///
+    /// Socket errors are returned through this type as well.
+    type Error: From<io::Error>;
+
+    /// Decode the next frame from what is left of `datagram`, returning `Ok(None)`
+    /// once there are no more frames in it.
+    fn decode_datagram(
+        &mut self,
+        datagram: &mut BytesMut,
+        meta: &RecvMeta,
+    ) -> Result<Option<Self::Item>, Self::Error>;
+
+    /// Called with an error about a datagram as a whole instead of a frame in it, an
+    /// `io::Error` wrapping a [`DatagramTruncated`] or [`TrailingBytes`]. Returns the
+    /// error for the `Stream` to yield, or `None` to carry on with the next datagram.
+    ///
+    /// The default converts `err` into `Self::Error`.
+    ///
+    /// [`DatagramTruncated`]: crate::DatagramTruncated
+    /// [`TrailingBytes`]: crate::TrailingBytes
+    fn datagram_error(&mut self, err: io::Error, meta: &RecvMeta) -> Option<Self::Error> {
+        let _ = meta;
+        Some(err.into())
+    }
+}
+
+/// Encodes frames as whole datagrams.
+///
+/// `encode_datagram` is called with an empty `dst` and everything it writes is sent
+/// as exactly one datagram.
+///
+/// Every `Encoder` is a `DatagramEncoder`, so existing codecs work as they are.
+pub trait DatagramEncoder<Item> {
+    /// The type of encoding errors.
+    ///
+    /// Socket errors are returned through this type as well.
+    type Error: From<io::Error>;
+
+    /// Encode `item` into `dst` as a single datagram.
+    fn encode_datagram(&mut self, item: Item, dst: &mut BytesMut) -> Result<(), Self::Error>;
+}
+
+impl<D> DatagramDecoder for D
+where
+    D: Decoder,
+{
+    type Item = D::Item;
+    type Error = D::Error;
+
+    fn decode_datagram(
+        &mut self,
+        datagram: &mut BytesMut,
+        _meta: &RecvMeta,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode_eof(datagram)
+    }
+}
+
+impl<E, Item> DatagramEncoder<Item> for E
+where
+    E: Encoder<Item>,
+{
+    type Error = E::Error;
+
+    fn encode_datagram(&mut self, item: Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode(item, dst)
+    }
+}
+
+/// Wraps a `Decoder` or `Encoder` so its errors, and the errors of the framed type it
+/// is used with, are returned as a [`UdpFramedError`].
+///
+/// Errors from the wrapped codec become `UdpFramedError::Codec`, errors raised by the
+/// framed type itself get their own variant. A `DatagramDecoder` or `DatagramEncoder`
+/// can use `UdpFramedError` as its error type directly.
+///
+/// [`UdpFramedError`]: crate::UdpFramedError
+#[derive(Debug, Clone, Default)]
+pub struct TypedErrors<C> {
+    codec: C,
+}
+
+impl<C> TypedErrors<C> {
+    /// Wrap `codec`.
+    pub fn new(codec: C) -> Self {
+        Self { codec }
+    }
+
+    /// Returns a reference to the wrapped codec.
+    pub fn get_ref(&self) -> &C {
+        &self.codec
+    }
+
+    /// Returns a mutable reference to the wrapped codec.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Consumes the `TypedErrors`, returning the wrapped codec.
+    pub fn into_inner(self) -> C {
+        self.codec
+    }
+}
+
+impl<C> Decoder for TypedErrors<C>
+where
+    C: Decoder,
+{
+    type Item = C::Item;
+    type Error = UdpFramedError<C::Error>;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.codec.decode(src).map_err(UdpFramedError::Codec)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.codec.decode_eof(src).map_err(UdpFramedError::Codec)
+    }
+}
+
+impl<C, Item> Encoder<Item> for TypedErrors<C>
+where
+    C: Encoder<Item>,
+{
+    type Error = UdpFramedError<C::Error>;
+
+    fn encode(&mut self, item: Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.codec.encode(item, dst).map_err(UdpFramedError::Codec)
+    }
+}
+
+/// Wraps a codec so that a datagram it fails to decode is skipped instead of ending
+/// up as an error from the `Stream`.
+///
+/// When the wrapped decoder returns an error, the error and the address of the sender
+/// are handed to `on_error`, the rest of the datagram is dropped and decoding carries
+/// on with the next datagram. Truncated datagrams and, with [`TrailingData::Error`],
+/// trailing bytes are handed to `on_error` as well. Socket errors are still returned
+/// from the `Stream`.
+///
+/// Encoding is passed through to the wrapped codec. See
+/// [`UdpFramed::resilient`] and [`UdpFramedRecv::resilient`].
+///
+/// [`UdpFramed::resilient`]: crate::UdpFramed::resilient
+/// [`UdpFramedRecv::resilient`]: crate::UdpFramedRecv::resilient
+/// [`TrailingData::Error`]: crate::TrailingData::Error
+pub struct Resilient<C, F> {
+    codec: C,
+    on_error: F,
+}
+
+impl<C, F> Resilient<C, F> {
+    /// Wrap `codec`, calling `on_error` for every datagram it fails to decode.
+    pub fn new(codec: C, on_error: F) -> Self {
+        Self { codec, on_error }
+    }
+
+    /// Returns a reference to the wrapped codec.
+    pub fn get_ref(&self) -> &C {
+        &self.codec
+    }
+
+    /// Returns a mutable reference to the wrapped codec.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Consumes the `Resilient`, returning the wrapped codec.
+    pub fn into_inner(self) -> C {
+        self.codec
+    }
+}
+
+impl<C, F> DatagramDecoder for Resilient<C, F>
+where
+    C: DatagramDecoder,
+    F: FnMut(C::Error, SocketAddr),
+{
+    type Item = C::Item;
+    type Error = C::Error;
+
+    fn decode_datagram(
+        &mut self,
+        datagram: &mut BytesMut,
+        meta: &RecvMeta,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        match self.codec.decode_datagram(datagram, meta) {
+            Ok(frame) => Ok(frame),
+            Err(err) => {
+                datagram.clear();
+                (self.on_error)(err, meta.addr);
+                Ok(None)
+            }
+        }
+    }
+
+    fn datagram_error(&mut self, err: io::Error, meta: &RecvMeta) -> Option<Self::Error> {
+        let err = self.codec.datagram_error(err, meta)?;
+        (self.on_error)(err, meta.addr);
+        None
+    }
+}
+
+// an `Encoder` rather than a `DatagramEncoder`, which the blanket impl makes it as well
+impl<C, F, Item> Encoder<Item> for Resilient<C, F>
+where
+    C: DatagramEncoder<Item>,
+{
+    type Error = C::Error;
+
+    fn encode(&mut self, item: Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.codec.encode_datagram(item, dst)
+    }
+}
+
+impl<C: fmt::Debug, F> fmt::Debug for Resilient<C, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resilient")
+            .field("codec", &self.codec)
+            .finish()
+    }
+}
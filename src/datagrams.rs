@@ -0,0 +1,200 @@
+use crate::{
+    builder::UdpFramedBuilder,
+    framed_impl::ReadFrame,
+    meta::{RecvMeta, WithMeta},
+};
+
+use tokio::net::UdpSocket;
+use tokio_stream::Stream;
+
+use bytes::Bytes;
+use futures_core::ready;
+use std::{
+    borrow::Borrow,
+    fmt, io, mem,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A [`Stream`] of the raw datagrams received on a `UdpSocket`, without a codec.
+///
+/// Each datagram is received straight into a pooled buffer and yielded as `Bytes`
+/// without being copied. Empty datagrams are yielded too.
+///
+/// [`Stream`]: tokio::stream::Stream
+pub struct UdpDatagrams<T> {
+    inner: T,
+    state: ReadFrame,
+}
+
+// the socket is only ever borrowed, never pinned
+impl<T> Unpin for UdpDatagrams<T> {}
+
+impl<T> UdpDatagrams<T>
+where
+    T: Borrow<UdpSocket>,
+{
+    /// Create a new `UdpDatagrams` backed by the given socket.
+    ///
+    /// See [`UdpFramedBuilder`] to configure buffer sizes and other options.
+    pub fn new(socket: T) -> UdpDatagrams<T> {
+        UdpFramedBuilder::new().build_datagrams(socket)
+    }
+
+    pub(crate) fn from_parts(socket: T, mut state: ReadFrame) -> UdpDatagrams<T> {
+        state.set_zero_copy(true);
+        Self {
+            inner: socket,
+            state,
+        }
+    }
+
+    /// Returns a reference to the underlying I/O stream wrapped by `UdpDatagrams`.
+    pub fn get_ref(&self) -> &UdpSocket {
+        self.inner.borrow()
+    }
+
+    /// Consumes the `UdpDatagrams`, returning its underlying I/O stream.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns the number of datagrams received per syscall.
+    pub fn recv_batch_size(&self) -> usize {
+        self.state.batch_size()
+    }
+
+    /// Receive up to `batch_size` datagrams per readiness event.
+    ///
+    /// See [`UdpFramedRecv::set_recv_batch_size`], batched datagrams are copied out of
+    /// the batch buffer.
+    ///
+    /// [`UdpFramedRecv::set_recv_batch_size`]: crate::UdpFramedRecv::set_recv_batch_size
+    pub fn set_recv_batch_size(&mut self, batch_size: usize) {
+        self.state.set_batch_size(batch_size);
+    }
+
+    /// Returns `true` if UDP generic receive offload is enabled.
+    pub fn recv_gro(&self) -> bool {
+        self.state.gro
+    }
+
+    /// Enable or disable UDP generic receive offload (`UDP_GRO`) on the socket.
+    ///
+    /// See [`UdpFramedRecv::set_recv_gro`].
+    ///
+    /// [`UdpFramedRecv::set_recv_gro`]: crate::UdpFramedRecv::set_recv_gro
+    pub fn set_recv_gro(&mut self, enabled: bool) -> io::Result<()> {
+        self.state.set_gro(self.inner.borrow(), enabled)
+    }
+
+    /// Returns the most bytes that will be received for a single datagram.
+    pub fn recv_max_datagram_size(&self) -> usize {
+        self.state.max_datagram_size
+    }
+
+    /// Sets the most bytes that will be received for a single datagram, 64KiB by default.
+    ///
+    /// See [`UdpFramedRecv::set_recv_max_datagram_size`].
+    ///
+    /// [`UdpFramedRecv::set_recv_max_datagram_size`]: crate::UdpFramedRecv::set_recv_max_datagram_size
+    pub fn set_recv_max_datagram_size(&mut self, max_datagram_size: usize) {
+        self.state.set_max_datagram_size(max_datagram_size);
+    }
+
+    /// Enable or disable reporting the local destination address and interface index
+    /// of received datagrams.
+    ///
+    /// See [`UdpFramedRecv::set_recv_pktinfo`].
+    ///
+    /// [`UdpFramedRecv::set_recv_pktinfo`]: crate::UdpFramedRecv::set_recv_pktinfo
+    pub fn set_recv_pktinfo(&mut self, enabled: bool) -> io::Result<()> {
+        self.state.set_pktinfo(self.inner.borrow(), enabled)
+    }
+
+    /// Enable or disable reporting the ECN codepoint of received datagrams.
+    ///
+    /// See [`UdpFramedRecv::set_recv_ecn`].
+    ///
+    /// [`UdpFramedRecv::set_recv_ecn`]: crate::UdpFramedRecv::set_recv_ecn
+    pub fn set_recv_ecn(&mut self, enabled: bool) -> io::Result<()> {
+        self.state.set_ecn(self.inner.borrow(), enabled)
+    }
+
+    /// Enable or disable kernel timestamps for received datagrams.
+    ///
+    /// See [`UdpFramedRecv::set_recv_timestamps`].
+    ///
+    /// [`UdpFramedRecv::set_recv_timestamps`]: crate::UdpFramedRecv::set_recv_timestamps
+    pub fn set_recv_timestamps(&mut self, enabled: bool) -> io::Result<()> {
+        self.state.set_timestamps(self.inner.borrow(), enabled)
+    }
+
+    /// Returns `true` if receive errors caused by ICMP messages are skipped.
+    pub fn recv_ignore_icmp_errors(&self) -> bool {
+        self.state.ignore_icmp_errors
+    }
+
+    /// Skip receive errors caused by ICMP messages about earlier sends.
+    ///
+    /// See [`UdpFramedRecv::set_recv_ignore_icmp_errors`].
+    ///
+    /// [`UdpFramedRecv::set_recv_ignore_icmp_errors`]: crate::UdpFramedRecv::set_recv_ignore_icmp_errors
+    pub fn set_recv_ignore_icmp_errors(&mut self, enabled: bool) {
+        self.state.ignore_icmp_errors = enabled;
+    }
+
+    /// Wrap this in a [`WithMeta`] whose `Stream` yields a [`RecvMeta`] with each
+    /// datagram instead of just the source address.
+    ///
+    /// [`WithMeta`]: crate::WithMeta
+    /// [`RecvMeta`]: crate::RecvMeta
+    pub fn with_meta(self) -> WithMeta<Self> {
+        WithMeta::new(self)
+    }
+
+    pub(crate) fn poll_next_meta(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<(Bytes, RecvMeta)>>> {
+        let info = ready!(self.state.poll_recv(cx, self.inner.borrow()))?;
+        if info.truncated {
+            let err = self.state.truncated_error(info.meta.addr);
+            return Poll::Ready(Some(Err(err)));
+        }
+        let datagram = mem::take(&mut self.state.buffer).freeze();
+        Poll::Ready(Some(Ok((datagram, info.meta))))
+    }
+}
+
+impl<T> Stream for UdpDatagrams<T>
+where
+    T: Borrow<UdpSocket>,
+{
+    type Item = io::Result<(Bytes, SocketAddr)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .poll_next_meta(cx)
+            .map(|next| next.map(|res| res.map(|(datagram, meta)| (datagram, meta.addr))))
+    }
+}
+
+impl<T> fmt::Debug for UdpDatagrams<T>
+where
+    T: Borrow<UdpSocket>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpDatagrams")
+            .field("io", self.get_ref())
+            .field("recv_batch_size", &self.recv_batch_size())
+            .field("recv_gro", &self.recv_gro())
+            .field("recv_max_datagram_size", &self.recv_max_datagram_size())
+            .field("recv_pktinfo", &self.state.pktinfo)
+            .field("recv_ecn", &self.state.ecn)
+            .field("recv_timestamps", &self.state.timestamps)
+            .field("recv_ignore_icmp_errors", &self.recv_ignore_icmp_errors())
+            .finish()
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{
+    codec::{DatagramDecoder, DatagramEncoder},
+    frame::UdpFramed,
+};
+
+use pin_project_lite::pin_project;
+use tokio::{
+    net::UdpSocket,
+    time::{self, Instant, Interval, MissedTickBehavior, Sleep},
+};
+use tokio_stream::Stream;
+
+use futures_core::ready;
+use futures_sink::Sink;
+use std::{
+    borrow::Borrow,
+    collections::HashSet,
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// How often the probe is sent, by default.
+const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+/// How long responders are collected for, by default.
+const DEFAULT_WINDOW: Duration = Duration::from_secs(3);
+
+pin_project! {
+    /// Finds the services on a network by sending a probe to a broadcast or multicast
+    /// address and collecting who responds, in the style of SSDP or mDNS.
+    ///
+    /// `Discovery` is a `Stream` of `(response, responder)` pairs. Once first polled it
+    /// sends the probe to the target address every probe interval, and yields the first
+    /// response from each address until the window has passed, when it ends. Later
+    /// responses from an address already seen are dropped, so every responder is
+    /// yielded once however many probes it answered.
+    ///
+    /// `SO_BROADCAST` is enabled on the socket on creation, to send to a multicast
+    /// group instead set the multicast interface and TTL on the `UdpFramed` as needed.
+    ///
+    /// ```rust
+    /// # use std::{io, net::SocketAddr, time::Duration};
+    /// # use tokio::net::UdpSocket;
+    /// # use tokio_stream::StreamExt;
+    /// use tokio_util::codec::LinesCodec;
+    /// use tokio_udp_framed::Discovery;
+    ///
+    /// # async fn run() -> io::Result<()> {
+    /// let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    /// let target: SocketAddr = "255.255.255.255:1900".parse().unwrap();
+    /// let mut discovery = Discovery::new(socket, LinesCodec::new(), "HELLO", target)?;
+    /// discovery.set_window(Duration::from_secs(5));
+    ///
+    /// while let Some(found) = discovery.next().await {
+    ///     let (name, addr) = found.unwrap();
+    ///     println!("{} is at {}", name, addr);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub struct Discovery<T, C, P> {
+        #[pin]
+        framed: UdpFramed<T, C>,
+        probe: P,
+        target: SocketAddr,
+        probe_interval: Duration,
+        window: Duration,
+        seen: HashSet<SocketAddr>,
+        // created on the first poll, since timers need the runtime
+        ticker: Option<Interval>,
+        deadline: Option<Pin<Box<Sleep>>>,
+        probe_due: bool,
+        flushing: bool,
+    }
+}
+
+impl<T, C, P> Discovery<T, C, P>
+where
+    T: Borrow<UdpSocket>,
+{
+    /// Create a new `Discovery` on `socket`, sending `probe` to `target` and decoding
+    /// responses with `codec`.
+    pub fn new(socket: T, codec: C, probe: P, target: SocketAddr) -> io::Result<Self> {
+        Self::from_framed(UdpFramed::new(socket, codec), probe, target)
+    }
+
+    /// Create a new `Discovery` from a configured `UdpFramed`, sending `probe` to
+    /// `target`.
+    pub fn from_framed(framed: UdpFramed<T, C>, probe: P, target: SocketAddr) -> io::Result<Self> {
+        framed.get_ref().set_broadcast(true)?;
+        Ok(Self {
+            framed,
+            probe,
+            target,
+            probe_interval: DEFAULT_PROBE_INTERVAL,
+            window: DEFAULT_WINDOW,
+            seen: HashSet::new(),
+            ticker: None,
+            deadline: None,
+            probe_due: false,
+            flushing: false,
+        })
+    }
+
+    /// Returns a reference to the underlying socket.
+    pub fn get_ref(&self) -> &UdpSocket {
+        self.framed.get_ref()
+    }
+
+    /// Returns the `UdpFramed` probes are sent and responses received with.
+    pub fn framed(&self) -> &UdpFramed<T, C> {
+        &self.framed
+    }
+
+    /// Returns a mutable reference to the `UdpFramed` probes are sent and responses
+    /// received with.
+    pub fn framed_mut(&mut self) -> &mut UdpFramed<T, C> {
+        &mut self.framed
+    }
+
+    /// Returns the address the probe is sent to.
+    pub fn target(&self) -> SocketAddr {
+        self.target
+    }
+
+    /// Returns how often the probe is sent.
+    pub fn probe_interval(&self) -> Duration {
+        self.probe_interval
+    }
+
+    /// Send the probe every `interval`. The default is 1 second, and it only applies
+    /// if set before the first poll.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero.
+    pub fn set_probe_interval(&mut self, interval: Duration) {
+        assert!(interval > Duration::ZERO, "probe interval must be non-zero");
+        self.probe_interval = interval;
+    }
+
+    /// Returns how long responders are collected for.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Collect responders for `window` from the first poll. The default is 3 seconds,
+    /// and it only applies if set before the first poll.
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Returns the addresses that have responded so far.
+    pub fn responders(&self) -> &HashSet<SocketAddr> {
+        &self.seen
+    }
+
+    /// Consumes the `Discovery`, returning the `UdpFramed` it used.
+    pub fn into_inner(self) -> UdpFramed<T, C> {
+        self.framed
+    }
+}
+
+impl<T, C, P> Stream for Discovery<T, C, P>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder + DatagramEncoder<P, Error = <C as DatagramDecoder>::Error>,
+    <C as DatagramDecoder>::Error: From<io::Error>,
+    P: Clone,
+{
+    type Item = Result<(C::Item, SocketAddr), <C as DatagramDecoder>::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        let window = *this.window;
+        let deadline = this
+            .deadline
+            .get_or_insert_with(|| Box::pin(time::sleep_until(Instant::now() + window)));
+        if deadline.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+
+        let probe_interval = *this.probe_interval;
+        let ticker = this.ticker.get_or_insert_with(|| {
+            let mut ticker = time::interval(probe_interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ticker
+        });
+        while ticker.poll_tick(cx).is_ready() {
+            *this.probe_due = true;
+        }
+
+        if *this.probe_due {
+            let sink = this.framed.as_mut();
+            if let Poll::Ready(ready) = Sink::<(P, SocketAddr)>::poll_ready(sink, cx) {
+                ready?;
+                let probe = (this.probe.clone(), *this.target);
+                this.framed.as_mut().start_send(probe)?;
+                *this.probe_due = false;
+                *this.flushing = true;
+            }
+        }
+        if *this.flushing {
+            let sink = this.framed.as_mut();
+            if let Poll::Ready(flushed) = Sink::<(P, SocketAddr)>::poll_flush(sink, cx) {
+                flushed?;
+                *this.flushing = false;
+            }
+        }
+
+        loop {
+            match ready!(this.framed.as_mut().poll_next(cx)) {
+                Some(Ok((response, addr))) => {
+                    if this.seen.insert(addr) {
+                        return Poll::Ready(Some(Ok((response, addr))));
+                    }
+                }
+                other => return Poll::Ready(other),
+            }
+        }
+    }
+}
+
+impl<T, C, P> fmt::Debug for Discovery<T, C, P>
+where
+    T: Borrow<UdpSocket>,
+    C: fmt::Debug,
+    P: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Discovery")
+            .field("framed", &self.framed)
+            .field("probe", &self.probe)
+            .field("target", &self.target)
+            .field("probe_interval", &self.probe_interval)
+            .field("window", &self.window)
+            .field("responders", &self.seen.len())
+            .finish()
+    }
+}
@@ -0,0 +1,187 @@
+use std::{error::Error, fmt, io, net::SocketAddr};
+
+/// A received datagram didn't fit in the space set aside for it and was cut short by
+/// the kernel.
+///
+/// It is returned from the `Stream` wrapped in an `io::Error` with kind `InvalidData`,
+/// the truncated datagram is dropped without being decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatagramTruncated {
+    /// The address the datagram came from.
+    pub addr: SocketAddr,
+    /// The most bytes that could be received for a single datagram.
+    pub max_datagram_size: usize,
+}
+
+impl fmt::Display for DatagramTruncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "datagram from {} was truncated to {} bytes",
+            self.addr, self.max_datagram_size
+        )
+    }
+}
+
+impl Error for DatagramTruncated {}
+
+/// An encoded frame was larger than the most bytes allowed in a single datagram.
+///
+/// It is returned from `start_send` wrapped in an `io::Error` with kind `InvalidInput`,
+/// converted into the codec's error type. Nothing is queued and the encode buffer is
+/// cleared, so the `Sink` can carry on with the next item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatagramTooLarge {
+    /// The length of the encoded frame.
+    pub len: usize,
+    /// The most bytes allowed in a single datagram.
+    pub max_datagram_size: usize,
+}
+
+impl fmt::Display for DatagramTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "encoded datagram of {} bytes is larger than the limit of {} bytes",
+            self.len, self.max_datagram_size
+        )
+    }
+}
+
+impl Error for DatagramTooLarge {}
+
+/// The kernel took only part of a datagram, or of a run of datagrams sent with
+/// segmentation offload.
+///
+/// It is returned from `poll_flush` wrapped in an `io::Error` with kind `Other`,
+/// converted into the codec's error type. The datagram isn't retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialSend {
+    /// The address the datagram was sent to.
+    pub addr: SocketAddr,
+    /// The length of the datagram.
+    pub len: usize,
+    /// The number of bytes the kernel took.
+    pub sent: usize,
+}
+
+impl fmt::Display for PartialSend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to write entire datagram to socket, sent {} of {} bytes to {}",
+            self.sent, self.len, self.addr
+        )
+    }
+}
+
+impl Error for PartialSend {}
+
+/// The decoder left bytes behind in a datagram and the [`TrailingData`] policy is
+/// `Error`.
+///
+/// It is returned from the `Stream` wrapped in an `io::Error` with kind `InvalidData`,
+/// converted into the codec's error type.
+///
+/// [`TrailingData`]: crate::TrailingData
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrailingBytes {
+    /// The address the datagram came from.
+    pub addr: SocketAddr,
+    /// The number of bytes that couldn't be decoded.
+    pub len: usize,
+}
+
+impl fmt::Display for TrailingBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} trailing bytes in datagram from {} could not be decoded",
+            self.len, self.addr
+        )
+    }
+}
+
+impl Error for TrailingBytes {}
+
+/// Every error the framed types can return, so each case can be matched on.
+///
+/// The framed types report their own errors as an `io::Error` wrapping one of the
+/// error structs in this crate, because codec errors only have to implement
+/// `From<io::Error>`. `UdpFramedError` implements that conversion by unwrapping those
+/// structs into their own variants, anything else is a `Socket` error. Use it as the
+/// error type of a codec, or wrap an existing codec in [`TypedErrors`] to have its
+/// errors returned as `Codec`.
+///
+/// Its `Display` includes the message of the error it wraps, so `source` returns that
+/// error's own source instead of the error itself.
+///
+/// [`TypedErrors`]: crate::TypedErrors
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum UdpFramedError<E = io::Error> {
+    /// Only part of a datagram was sent.
+    PartialSend(PartialSend),
+    /// An encoded frame was larger than the send limit.
+    TooLarge(DatagramTooLarge),
+    /// A received datagram was larger than the receive limit.
+    Truncated(DatagramTruncated),
+    /// The decoder left bytes behind in a datagram.
+    Trailing(TrailingBytes),
+    /// The codec failed to encode or decode a frame.
+    Codec(E),
+    /// A send or receive on the socket failed.
+    Socket(io::Error),
+}
+
+impl<E> From<io::Error> for UdpFramedError<E> {
+    fn from(err: io::Error) -> Self {
+        if err.get_ref().is_none() {
+            return UdpFramedError::Socket(err);
+        }
+        let kind = err.kind();
+        let inner = err.into_inner().expect("checked above");
+        let inner = match inner.downcast::<PartialSend>() {
+            Ok(err) => return UdpFramedError::PartialSend(*err),
+            Err(inner) => inner,
+        };
+        let inner = match inner.downcast::<DatagramTooLarge>() {
+            Ok(err) => return UdpFramedError::TooLarge(*err),
+            Err(inner) => inner,
+        };
+        let inner = match inner.downcast::<DatagramTruncated>() {
+            Ok(err) => return UdpFramedError::Truncated(*err),
+            Err(inner) => inner,
+        };
+        match inner.downcast::<TrailingBytes>() {
+            Ok(err) => UdpFramedError::Trailing(*err),
+            Err(inner) => UdpFramedError::Socket(io::Error::new(kind, inner)),
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for UdpFramedError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UdpFramedError::PartialSend(err) => err.fmt(f),
+            UdpFramedError::TooLarge(err) => err.fmt(f),
+            UdpFramedError::Truncated(err) => err.fmt(f),
+            UdpFramedError::Trailing(err) => err.fmt(f),
+            UdpFramedError::Codec(err) => write!(f, "codec error: {}", err),
+            UdpFramedError::Socket(err) => write!(f, "socket error: {}", err),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for UdpFramedError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UdpFramedError::PartialSend(err) => err.source(),
+            UdpFramedError::TooLarge(err) => err.source(),
+            UdpFramedError::Truncated(err) => err.source(),
+            UdpFramedError::Trailing(err) => err.source(),
+            UdpFramedError::Codec(err) => err.source(),
+            UdpFramedError::Socket(err) => err.source(),
+        }
+    }
+}
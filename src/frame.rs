@@ -1,6 +1,10 @@
-use tokio_util::codec::{Decoder, Encoder};
-
-use crate::framed_impl::{RWFrames, ReadFrame, UdpFramedImpl, WriteFrame};
+use crate::{
+    builder::UdpFramedBuilder,
+    codec::{DatagramDecoder, DatagramEncoder, Resilient},
+    framed_impl::{RWFrames, UdpFramedImpl},
+    meta::{MetaItem, SendMeta, WithMeta},
+    trailing::TrailingData,
+};
 
 use pin_project_lite::pin_project;
 use tokio::net::UdpSocket;
@@ -11,14 +15,15 @@ use futures_sink::Sink;
 use std::{
     borrow::Borrow,
     fmt, io,
-    net::SocketAddr,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     pin::Pin,
     task::{Context, Poll},
 };
 
 pin_project! {
     /// A unified [`Stream`] and [`Sink`] interface to an underlying `UdpSocket`, using
-    /// the `Encoder` and `Decoder` traits to encode and decode frames.
+    /// the [`DatagramEncoder`] and [`DatagramDecoder`] traits to encode and decode
+    /// frames. Any `Encoder` and `Decoder` from `tokio-util` can be used as well.
     ///
     /// Raw UDP sockets work with datagrams, but higher-level code usually wants to
     /// batch these into meaningful chunks, called "frames". This method layers
@@ -37,6 +42,8 @@ pin_project! {
     /// [`Stream`]: tokio::stream::Stream
     /// [`Sink`]: futures_sink::Sink
     /// [`split`]: https://docs.rs/futures/0.3/futures/stream/trait.StreamExt.html#method.split.
+    /// [`DatagramEncoder`]: crate::DatagramEncoder
+    /// [`DatagramDecoder`]: crate::DatagramDecoder
     pub struct UdpFramed<T, C> {
         #[pin]
         inner: UdpFramedImpl<T, C, RWFrames>,
@@ -46,7 +53,7 @@ pin_project! {
 impl<T, C> Stream for UdpFramed<T, C>
 where
     T: Borrow<UdpSocket>,
-    C: Decoder,
+    C: DatagramDecoder,
 {
     type Item = Result<(C::Item, SocketAddr), C::Error>;
 
@@ -55,11 +62,40 @@ where
     }
 }
 
+impl<T, C> UdpFramed<T, C>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder,
+{
+    pub(crate) fn poll_next_meta(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<MetaItem<C>>> {
+        self.project().inner.poll_next_meta(cx)
+    }
+}
+
+impl<T, C> UdpFramed<T, C>
+where
+    T: Borrow<UdpSocket>,
+{
+    pub(crate) fn start_send_meta<I>(
+        self: Pin<&mut Self>,
+        frame: I,
+        meta: SendMeta,
+    ) -> Result<(), C::Error>
+    where
+        C: DatagramEncoder<I>,
+    {
+        self.project().inner.start_send_meta(frame, meta)
+    }
+}
+
 // This impl just defers to the underlying FramedImpl
 impl<T, I, C> Sink<(I, SocketAddr)> for UdpFramed<T, C>
 where
     T: Borrow<UdpSocket>,
-    C: Encoder<I>,
+    C: DatagramEncoder<I>,
     C::Error: From<io::Error>,
 {
     type Error = C::Error;
@@ -87,28 +123,29 @@ where
 {
     /// Create a new `UdpFramed` backed by the given socket and codec.
     ///
-    /// See struct level documentation for more details.
+    /// See struct level documentation for more details, or [`UdpFramedBuilder`] to
+    /// configure buffer sizes and other options.
     pub fn new(socket: T, codec: C) -> UdpFramed<T, C> {
+        UdpFramedBuilder::new().build(socket, codec)
+    }
+
+    pub(crate) fn from_parts(socket: T, codec: C, state: RWFrames) -> UdpFramed<T, C> {
         Self {
             inner: UdpFramedImpl {
                 inner: socket,
                 codec,
-                state: RWFrames {
-                    read: ReadFrame {
-                        buffer: BytesMut::with_capacity(crate::framed_impl::INITIAL_RD_CAPACITY),
-                        ..ReadFrame::default()
-                    },
-                    write: WriteFrame {
-                        buffer: BytesMut::with_capacity(crate::framed_impl::INITIAL_WR_CAPACITY),
-                    },
-                },
-                out_addr: ([0, 0, 0, 0], 0).into(),
-                flushed: true,
-                current_addr: None,
+                state,
+                current_meta: None,
             },
         }
     }
 
+    pub(crate) fn map_codec<V>(self, f: impl FnOnce(C) -> V) -> UdpFramed<T, V> {
+        UdpFramed {
+            inner: self.inner.map_codec(f),
+        }
+    }
+
     /// Returns a reference to the underlying I/O stream wrapped by `Framed`.
     ///
     /// # Note
@@ -117,7 +154,7 @@ where
     /// coming in as it may corrupt the stream of frames otherwise being worked
     /// with.
     pub fn get_ref(&self) -> &UdpSocket {
-        &self.inner.inner.borrow()
+        self.inner.inner.borrow()
     }
 
     /// Returns a reference to the underlying codec wrapped by
@@ -148,6 +185,263 @@ where
         &mut self.inner.state.read.buffer
     }
 
+    /// Returns the number of datagrams received per syscall, `1` unless batching
+    /// has been enabled with [`set_recv_batch_size`].
+    ///
+    /// [`set_recv_batch_size`]: Self::set_recv_batch_size
+    pub fn recv_batch_size(&self) -> usize {
+        self.inner.state.read.batch_size()
+    }
+
+    /// Receive up to `batch_size` datagrams per readiness event.
+    ///
+    /// On Linux this uses a single `recvmmsg` call per batch, on other platforms
+    /// datagrams are still received one at a time. Each datagram keeps its own
+    /// source address and is decoded on its own. A `batch_size` of `0` or `1`
+    /// disables batching.
+    ///
+    /// The batch buffer is allocated up front with one slot per datagram, each
+    /// [`recv_max_datagram_size`] bytes long, or at least 64 KiB when GRO is enabled.
+    /// With the default 64 KiB maximum a batch of 64 takes 4 MiB, lower the maximum
+    /// with [`set_recv_max_datagram_size`] if datagrams are known to be smaller.
+    ///
+    /// This should be set before receiving, any datagrams left over from a previous
+    /// batch are dropped.
+    ///
+    /// [`recv_max_datagram_size`]: Self::recv_max_datagram_size
+    /// [`set_recv_max_datagram_size`]: Self::set_recv_max_datagram_size
+    pub fn set_recv_batch_size(&mut self, batch_size: usize) {
+        self.inner.state.read.set_batch_size(batch_size);
+    }
+
+    /// Returns `true` if UDP generic receive offload is enabled.
+    pub fn recv_gro(&self) -> bool {
+        self.inner.state.read.gro
+    }
+
+    /// Enable or disable UDP generic receive offload (`UDP_GRO`) on the socket.
+    ///
+    /// With GRO the kernel may coalesce several datagrams from the same sender into
+    /// a single read. They are split back apart at the segment size the kernel
+    /// reports, and each one is decoded on its own and tagged with the sender's
+    /// address. Returns an error if the socket option can't be set, GRO is only
+    /// available on Linux.
+    pub fn set_recv_gro(&mut self, enabled: bool) -> io::Result<()> {
+        let socket = self.inner.inner.borrow();
+        self.inner.state.read.set_gro(socket, enabled)
+    }
+
+    /// Returns `true` if the destination address and interface of received datagrams
+    /// are reported.
+    pub fn recv_pktinfo(&self) -> bool {
+        self.inner.state.read.pktinfo
+    }
+
+    /// Enable or disable reporting the local destination address and interface index
+    /// of received datagrams (`IP_PKTINFO` / `IPV6_RECVPKTINFO`).
+    ///
+    /// These show up in the [`RecvMeta`] yielded by [`with_meta`]. Returns an error
+    /// if the socket option can't be set, this is only available on Linux and Apple
+    /// platforms.
+    ///
+    /// [`RecvMeta`]: crate::RecvMeta
+    /// [`with_meta`]: UdpFramed::with_meta
+    pub fn set_recv_pktinfo(&mut self, enabled: bool) -> io::Result<()> {
+        let socket = self.inner.inner.borrow();
+        self.inner.state.read.set_pktinfo(socket, enabled)
+    }
+
+    /// Returns `true` if the ECN codepoint of received datagrams is reported.
+    pub fn recv_ecn(&self) -> bool {
+        self.inner.state.read.ecn
+    }
+
+    /// Enable or disable reporting the ECN codepoint of received datagrams
+    /// (`IP_RECVTOS` / `IPV6_RECVTCLASS`).
+    ///
+    /// The codepoint shows up in the [`RecvMeta`] yielded by [`with_meta`]. Returns an
+    /// error if the socket option can't be set, this is only available on Linux.
+    ///
+    /// [`RecvMeta`]: crate::RecvMeta
+    /// [`with_meta`]: UdpFramed::with_meta
+    pub fn set_recv_ecn(&mut self, enabled: bool) -> io::Result<()> {
+        let socket = self.inner.inner.borrow();
+        self.inner.state.read.set_ecn(socket, enabled)
+    }
+
+    /// Returns `true` if received datagrams are timestamped by the kernel.
+    pub fn recv_timestamps(&self) -> bool {
+        self.inner.state.read.timestamps
+    }
+
+    /// Enable or disable kernel timestamps for received datagrams (`SO_TIMESTAMPNS`).
+    ///
+    /// The time each datagram arrived shows up in the [`RecvMeta`] yielded by
+    /// [`with_meta`], datagrams coalesced by GRO share a timestamp. Returns an error if
+    /// the socket option can't be set, this is only available on Linux.
+    ///
+    /// [`RecvMeta`]: crate::RecvMeta
+    /// [`with_meta`]: UdpFramed::with_meta
+    pub fn set_recv_timestamps(&mut self, enabled: bool) -> io::Result<()> {
+        let socket = self.inner.inner.borrow();
+        self.inner.state.read.set_timestamps(socket, enabled)
+    }
+
+    /// Returns `true` if each datagram is split off into its own buffer before
+    /// decoding.
+    pub fn recv_zero_copy(&self) -> bool {
+        self.inner.state.read.zero_copy
+    }
+
+    /// Split each received datagram off the read buffer into its own `BytesMut`
+    /// before decoding it.
+    ///
+    /// A codec can then `freeze` the datagram, or `split_to` parts of it, and yield
+    /// `Bytes` without copying. Instead of clearing the read buffer for the next
+    /// datagram it is refilled from a small pool of buffers whose frames have all been
+    /// dropped. Datagrams received in batches are still copied once out of the batch
+    /// buffer. This should be set before receiving.
+    pub fn set_recv_zero_copy(&mut self, enabled: bool) {
+        self.inner.state.read.set_zero_copy(enabled);
+    }
+
+    /// Returns `true` if receive errors caused by ICMP messages are skipped.
+    pub fn recv_ignore_icmp_errors(&self) -> bool {
+        self.inner.state.read.ignore_icmp_errors
+    }
+
+    /// Skip receive errors caused by ICMP messages about earlier sends.
+    ///
+    /// After a datagram is sent to a closed port or an unreachable host, the kernel
+    /// may report the ICMP error it got back on the next receive, as
+    /// `ConnectionRefused`, `HostUnreachable` or `NetworkUnreachable` (`ConnectionReset`
+    /// on Windows). On Linux this happens for connected sockets and sockets with
+    /// `IP_RECVERR` set. These errors say nothing about the datagrams still to be
+    /// received, so with this enabled they are dropped and receiving carries on.
+    pub fn set_recv_ignore_icmp_errors(&mut self, enabled: bool) {
+        self.inner.state.read.ignore_icmp_errors = enabled;
+    }
+
+    /// Wrap this in a [`WithMeta`] whose `Stream` yields a [`RecvMeta`] with each frame
+    /// instead of just the source address, and whose `Sink` takes a [`SendMeta`]
+    /// instead of just the destination address.
+    ///
+    /// [`WithMeta`]: crate::WithMeta
+    /// [`RecvMeta`]: crate::RecvMeta
+    /// [`SendMeta`]: crate::SendMeta
+    pub fn with_meta(self) -> WithMeta<Self> {
+        WithMeta::new(self)
+    }
+
+    /// Join the IPv4 multicast `group` on the interface with address `interface`, or
+    /// `Ipv4Addr::UNSPECIFIED` to let the kernel pick one.
+    ///
+    /// Enable [`set_recv_pktinfo`] to see which group each frame was sent to in
+    /// [`RecvMeta::dst_ip`].
+    ///
+    /// [`set_recv_pktinfo`]: UdpFramed::set_recv_pktinfo
+    /// [`RecvMeta::dst_ip`]: crate::RecvMeta::dst_ip
+    pub fn join_multicast_v4(&self, group: Ipv4Addr, interface: Ipv4Addr) -> io::Result<()> {
+        self.get_ref().join_multicast_v4(group, interface)
+    }
+
+    /// Leave an IPv4 multicast group joined with [`join_multicast_v4`].
+    ///
+    /// [`join_multicast_v4`]: UdpFramed::join_multicast_v4
+    pub fn leave_multicast_v4(&self, group: Ipv4Addr, interface: Ipv4Addr) -> io::Result<()> {
+        self.get_ref().leave_multicast_v4(group, interface)
+    }
+
+    /// Join the IPv6 multicast `group` on the interface with index `ifindex`, or 0 to
+    /// let the kernel pick one.
+    pub fn join_multicast_v6(&self, group: &Ipv6Addr, ifindex: u32) -> io::Result<()> {
+        self.get_ref().join_multicast_v6(group, ifindex)
+    }
+
+    /// Leave an IPv6 multicast group joined with [`join_multicast_v6`].
+    ///
+    /// [`join_multicast_v6`]: UdpFramed::join_multicast_v6
+    pub fn leave_multicast_v6(&self, group: &Ipv6Addr, ifindex: u32) -> io::Result<()> {
+        self.get_ref().leave_multicast_v6(group, ifindex)
+    }
+
+    /// Join the IPv4 multicast `group` for what `source` sends to it only, on the
+    /// interface with address `interface` (source-specific multicast).
+    ///
+    /// This uses `IP_ADD_SOURCE_MEMBERSHIP`, which is available on Linux, Android,
+    /// FreeBSD and Apple platforms.
+    pub fn join_source_multicast_v4(
+        &self,
+        group: Ipv4Addr,
+        source: Ipv4Addr,
+        interface: Ipv4Addr,
+    ) -> io::Result<()> {
+        crate::sys::set_source_membership_v4(self.get_ref(), group, source, interface, true)
+    }
+
+    /// Leave a source-specific IPv4 multicast group joined with
+    /// [`join_source_multicast_v4`].
+    ///
+    /// [`join_source_multicast_v4`]: UdpFramed::join_source_multicast_v4
+    pub fn leave_source_multicast_v4(
+        &self,
+        group: Ipv4Addr,
+        source: Ipv4Addr,
+        interface: Ipv4Addr,
+    ) -> io::Result<()> {
+        crate::sys::set_source_membership_v4(self.get_ref(), group, source, interface, false)
+    }
+
+    /// Skip datagrams the codec fails to decode instead of returning the error from
+    /// the `Stream`.
+    ///
+    /// The error and the address of the sender are handed to `on_error`, whatever is
+    /// left of the datagram is dropped and the `Stream` carries on with the next one.
+    /// Truncated datagrams and trailing bytes rejected by [`TrailingData::Error`] are
+    /// skipped the same way. Socket errors are still returned. See [`Resilient`].
+    ///
+    /// [`Resilient`]: crate::Resilient
+    /// [`TrailingData::Error`]: crate::TrailingData::Error
+    pub fn resilient<F>(self, on_error: F) -> UdpFramed<T, Resilient<C, F>>
+    where
+        C: DatagramDecoder,
+        F: FnMut(C::Error, SocketAddr),
+    {
+        self.map_codec(|codec| Resilient::new(codec, on_error))
+    }
+
+    /// Returns the most bytes that will be received for a single datagram.
+    pub fn recv_max_datagram_size(&self) -> usize {
+        self.inner.state.read.max_datagram_size
+    }
+
+    /// Sets the most bytes that will be received for a single datagram, 64KiB by default.
+    ///
+    /// The kernel truncates datagrams larger than this. A truncated datagram is
+    /// dropped without being decoded and the `Stream` yields an `io::Error` of kind
+    /// `InvalidData` wrapping a [`DatagramTruncated`] instead. Truncation can't be
+    /// detected on platforms without `recvmsg`. A size of 0 is raised to 1.
+    ///
+    /// [`DatagramTruncated`]: crate::DatagramTruncated
+    pub fn set_recv_max_datagram_size(&mut self, max_datagram_size: usize) {
+        self.inner
+            .state
+            .read
+            .set_max_datagram_size(max_datagram_size);
+    }
+
+    /// Returns the policy for bytes left over in a datagram after decoding.
+    pub fn trailing_data(&self) -> &TrailingData {
+        &self.inner.state.read.trailing
+    }
+
+    /// Sets what to do with bytes the decoder leaves behind in a datagram.
+    ///
+    /// By default they are discarded, see [`TrailingData`] for the alternatives.
+    pub fn set_trailing_data(&mut self, policy: TrailingData) {
+        self.inner.state.read.trailing = policy;
+    }
+
     /// Returns a reference to the write buffer.
     pub fn write_buffer(&self) -> &BytesMut {
         &self.inner.state.write.buffer
@@ -158,6 +452,101 @@ where
         &mut self.inner.state.write.buffer
     }
 
+    /// Returns the number of datagrams that may be queued by the `Sink` before
+    /// `poll_ready` waits for them to be flushed.
+    pub fn send_high_water_mark(&self) -> usize {
+        self.inner.state.write.high_water_mark
+    }
+
+    /// Sets the number of datagrams that may be queued by the `Sink` before
+    /// `poll_ready` waits for them to be flushed.
+    ///
+    /// Every item passed to `start_send` is encoded into its own datagram and sent
+    /// to its own address. A value of `0` or `1` flushes before accepting each item.
+    /// A datagram the kernel refuses to send, for example to an invalid address, is
+    /// dropped from the queue and its error returned by `poll_flush`, flushing again
+    /// sends the rest.
+    pub fn set_send_high_water_mark(&mut self, hwm: usize) {
+        self.inner.state.write.high_water_mark = hwm;
+    }
+
+    /// Returns the most bytes allowed in a single encoded datagram.
+    pub fn send_max_datagram_size(&self) -> usize {
+        self.inner.state.write.max_datagram_size
+    }
+
+    /// Sets the most bytes allowed in a single encoded datagram, 65507 by default.
+    ///
+    /// Larger frames are rejected by `start_send` with an `io::Error` of kind
+    /// `InvalidInput` wrapping a [`DatagramTooLarge`], converted into the codec's
+    /// error type. Nothing is sent for them.
+    ///
+    /// [`DatagramTooLarge`]: crate::DatagramTooLarge
+    pub fn set_send_max_datagram_size(&mut self, max_datagram_size: usize) {
+        self.inner.state.write.max_datagram_size = max_datagram_size;
+    }
+
+    /// Returns `true` if runs of queued datagrams are sent using UDP generic
+    /// segmentation offload.
+    pub fn send_segmentation_offload(&self) -> bool {
+        self.inner.state.write.gso
+    }
+
+    /// Enable or disable UDP generic segmentation offload (`UDP_SEGMENT`) for sends.
+    ///
+    /// When enabled, consecutive queued datagrams going to the same address with the
+    /// same encoded size are handed to the kernel as one buffer, which is split back
+    /// into the individual datagrams on the way out. Support is checked at runtime,
+    /// this returns `false` and keeps sending one datagram at a time if the platform
+    /// or kernel can't do segmentation offload. It is also turned off again if the
+    /// device can't segment a send, and datagrams too large for the path MTU to be
+    /// segmented are sent on their own.
+    pub fn set_send_segmentation_offload(&mut self, enabled: bool) -> bool {
+        let enabled = enabled && crate::sys::gso_supported(self.get_ref());
+
+        self.inner.state.write.gso = enabled;
+        self.inner.state.write.gso_max_segment = usize::MAX;
+        enabled
+    }
+
+    /// Set the time-to-live of IPv4 multicast datagrams, how many routers they may
+    /// cross. The kernel default is 1, which keeps them on the local network.
+    pub fn set_multicast_ttl_v4(&self, ttl: u32) -> io::Result<()> {
+        self.get_ref().set_multicast_ttl_v4(ttl)
+    }
+
+    /// Set the hop limit of IPv6 multicast datagrams, the IPv6 counterpart of
+    /// [`set_multicast_ttl_v4`].
+    ///
+    /// [`set_multicast_ttl_v4`]: UdpFramed::set_multicast_ttl_v4
+    pub fn set_multicast_hops_v6(&self, hops: u32) -> io::Result<()> {
+        crate::sys::set_multicast_hops_v6(self.get_ref(), hops)
+    }
+
+    /// Set whether IPv4 multicast datagrams are looped back to sockets on this host
+    /// that joined the group. The kernel default is `true`.
+    pub fn set_multicast_loop_v4(&self, enabled: bool) -> io::Result<()> {
+        self.get_ref().set_multicast_loop_v4(enabled)
+    }
+
+    /// Set whether IPv6 multicast datagrams are looped back to sockets on this host
+    /// that joined the group. The kernel default is `true`.
+    pub fn set_multicast_loop_v6(&self, enabled: bool) -> io::Result<()> {
+        self.get_ref().set_multicast_loop_v6(enabled)
+    }
+
+    /// Send IPv4 multicast datagrams out of the interface with address `interface`
+    /// instead of the one the routing table picks.
+    pub fn set_multicast_interface_v4(&self, interface: Ipv4Addr) -> io::Result<()> {
+        crate::sys::set_multicast_if_v4(self.get_ref(), interface)
+    }
+
+    /// Send IPv6 multicast datagrams out of the interface with index `ifindex` instead
+    /// of the one the routing table picks.
+    pub fn set_multicast_interface_v6(&self, ifindex: u32) -> io::Result<()> {
+        crate::sys::set_multicast_if_v6(self.get_ref(), ifindex)
+    }
+
     /// Consumes the `Framed`, returning its underlying I/O stream.
     pub fn into_inner(self) -> T {
         self.inner.inner
@@ -173,10 +562,24 @@ where
         f.debug_struct("UdpFramed")
             .field("io", self.get_ref())
             .field("codec", self.codec())
-            .field("current_addr", &self.inner.current_addr)
-            .field("flushed", &self.inner.flushed)
-            .field("out_addr", &self.inner.out_addr)
+            .field("current_meta", &self.inner.current_meta)
+            .field("send_queue", &self.inner.state.write.queue)
+            .field("send_high_water_mark", &self.send_high_water_mark())
+            .field("send_max_datagram_size", &self.send_max_datagram_size())
+            .field(
+                "send_segmentation_offload",
+                &self.send_segmentation_offload(),
+            )
             .field("is_readable", &self.inner.state.read.is_readable)
+            .field("recv_batch_size", &self.recv_batch_size())
+            .field("recv_gro", &self.recv_gro())
+            .field("recv_max_datagram_size", &self.recv_max_datagram_size())
+            .field("recv_pktinfo", &self.recv_pktinfo())
+            .field("recv_ecn", &self.recv_ecn())
+            .field("recv_timestamps", &self.recv_timestamps())
+            .field("recv_zero_copy", &self.recv_zero_copy())
+            .field("recv_ignore_icmp_errors", &self.recv_ignore_icmp_errors())
+            .field("trailing_data", self.trailing_data())
             .field("eof", &self.inner.state.read.eof)
             .field("read_buffer", &self.read_buffer())
             .field("write_buffer", &self.write_buffer())
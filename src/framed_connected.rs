@@ -0,0 +1,188 @@
+use crate::{
+    builder::UdpFramedBuilder,
+    codec::{DatagramDecoder, DatagramEncoder},
+    framed_impl::{RWFrames, UdpFramedImpl},
+};
+
+use pin_project_lite::pin_project;
+use tokio::net::UdpSocket;
+use tokio_stream::Stream;
+
+use bytes::BytesMut;
+use futures_sink::Sink;
+use std::{
+    borrow::Borrow,
+    fmt, io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pin_project! {
+    /// A unified [`Stream`] and [`Sink`] interface to a `UdpSocket` that has been
+    /// [`connect`]ed to a single peer.
+    ///
+    /// This works like [`UdpFramed`] except that frames are sent to and received from
+    /// the connected peer, so the `Sink` takes plain items and the `Stream` yields
+    /// them without a `SocketAddr`.
+    ///
+    /// [`Stream`]: tokio::stream::Stream
+    /// [`Sink`]: futures_sink::Sink
+    /// [`connect`]: tokio::net::UdpSocket::connect
+    /// [`UdpFramed`]: crate::UdpFramed
+    pub struct UdpFramedConnected<T, C> {
+        #[pin]
+        inner: UdpFramedImpl<T, C, RWFrames>,
+        peer: SocketAddr,
+    }
+}
+
+impl<T, C> Stream for UdpFramedConnected<T, C>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder,
+{
+    type Item = Result<C::Item, C::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project()
+            .inner
+            .poll_next(cx)
+            .map(|next| next.map(|res| res.map(|(frame, _)| frame)))
+    }
+}
+
+impl<T, I, C> Sink<I> for UdpFramedConnected<T, C>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramEncoder<I>,
+    C::Error: From<io::Error>,
+{
+    type Error = C::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let pin = self.project();
+        pin.inner.start_send((item, *pin.peer))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+impl<T, C> UdpFramedConnected<T, C>
+where
+    T: Borrow<UdpSocket>,
+{
+    /// Create a new `UdpFramedConnected` backed by the given connected socket and codec.
+    ///
+    /// Returns an error if the socket hasn't been connected. See [`UdpFramedBuilder`]
+    /// to configure buffer sizes and other options.
+    pub fn new(socket: T, codec: C) -> io::Result<UdpFramedConnected<T, C>> {
+        UdpFramedBuilder::new().build_connected(socket, codec)
+    }
+
+    pub(crate) fn from_parts(
+        socket: T,
+        codec: C,
+        mut state: RWFrames,
+    ) -> io::Result<UdpFramedConnected<T, C>> {
+        let peer = socket.borrow().peer_addr()?;
+        state.write.connected = true;
+        Ok(Self {
+            inner: UdpFramedImpl {
+                inner: socket,
+                codec,
+                state,
+                current_meta: None,
+            },
+            peer,
+        })
+    }
+
+    /// Returns a reference to the underlying I/O stream wrapped by `Framed`.
+    ///
+    /// # Note
+    ///
+    /// Care should be taken to not tamper with the underlying stream of data
+    /// coming in as it may corrupt the stream of frames otherwise being worked
+    /// with.
+    pub fn get_ref(&self) -> &UdpSocket {
+        self.inner.inner.borrow()
+    }
+
+    /// Returns the address of the peer the socket is connected to.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Returns a reference to the underlying codec wrapped by
+    /// `Framed`.
+    ///
+    /// Note that care should be taken to not tamper with the underlying codec
+    /// as it may corrupt the stream of frames otherwise being worked with.
+    pub fn codec(&self) -> &C {
+        &self.inner.codec
+    }
+
+    /// Returns a mutable reference to the underlying codec wrapped by
+    /// `UdpFramedConnected`.
+    ///
+    /// Note that care should be taken to not tamper with the underlying codec
+    /// as it may corrupt the stream of frames otherwise being worked with.
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.inner.codec
+    }
+
+    /// Returns a reference to the read buffer.
+    pub fn read_buffer(&self) -> &BytesMut {
+        &self.inner.state.read.buffer
+    }
+
+    /// Returns a mutable reference to the read buffer.
+    pub fn read_buffer_mut(&mut self) -> &mut BytesMut {
+        &mut self.inner.state.read.buffer
+    }
+
+    /// Returns a reference to the write buffer.
+    pub fn write_buffer(&self) -> &BytesMut {
+        &self.inner.state.write.buffer
+    }
+
+    /// Returns a mutable reference to the write buffer.
+    pub fn write_buffer_mut(&mut self) -> &mut BytesMut {
+        &mut self.inner.state.write.buffer
+    }
+
+    /// Consumes the `Framed`, returning its underlying I/O stream.
+    pub fn into_inner(self) -> T {
+        self.inner.inner
+    }
+}
+
+impl<T, C> fmt::Debug for UdpFramedConnected<T, C>
+where
+    T: Borrow<UdpSocket>,
+    C: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpFramedConnected")
+            .field("io", self.get_ref())
+            .field("codec", self.codec())
+            .field("peer", &self.peer)
+            .field("send_queue", &self.inner.state.write.queue)
+            .field("is_readable", &self.inner.state.read.is_readable)
+            .field("eof", &self.inner.state.read.eof)
+            .field("read_buffer", &self.read_buffer())
+            .field("write_buffer", &self.write_buffer())
+            .finish()
+    }
+}
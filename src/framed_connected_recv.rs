@@ -0,0 +1,145 @@
+use crate::{
+    builder::UdpFramedBuilder,
+    codec::DatagramDecoder,
+    framed_impl::{ReadFrame, UdpFramedImpl},
+};
+
+use pin_project_lite::pin_project;
+use tokio::net::UdpSocket;
+use tokio_stream::Stream;
+
+use bytes::BytesMut;
+use std::{
+    borrow::Borrow,
+    fmt, io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pin_project! {
+    /// A [`Stream`] of messages decoded from a [`connect`]ed [`UdpSocket`].
+    ///
+    /// Datagrams only come from the connected peer, so frames are yielded without
+    /// a `SocketAddr`.
+    ///
+    /// [`Stream`]: tokio::stream::Stream
+    /// [`connect`]: tokio::net::UdpSocket::connect
+    pub struct UdpFramedConnectedRecv<T, C> {
+        #[pin]
+        inner: UdpFramedImpl<T, C, ReadFrame>,
+        peer: SocketAddr,
+    }
+}
+
+impl<T, C> UdpFramedConnectedRecv<T, C>
+where
+    T: Borrow<UdpSocket>,
+{
+    /// Create a new `UdpFramedConnectedRecv` backed by the given connected socket and
+    /// codec.
+    ///
+    /// Returns an error if the socket hasn't been connected. See [`UdpFramedBuilder`]
+    /// to configure buffer sizes and other options.
+    pub fn new(socket: T, codec: C) -> io::Result<UdpFramedConnectedRecv<T, C>> {
+        UdpFramedBuilder::new().build_connected_recv(socket, codec)
+    }
+
+    pub(crate) fn from_parts(
+        socket: T,
+        codec: C,
+        state: ReadFrame,
+    ) -> io::Result<UdpFramedConnectedRecv<T, C>> {
+        let peer = socket.borrow().peer_addr()?;
+        Ok(Self {
+            inner: UdpFramedImpl {
+                inner: socket,
+                codec,
+                state,
+                current_meta: None,
+            },
+            peer,
+        })
+    }
+
+    /// Returns a reference to the underlying I/O stream wrapped by `Framed`.
+    ///
+    /// # Note
+    ///
+    /// Care should be taken to not tamper with the underlying stream of data
+    /// coming in as it may corrupt the stream of frames otherwise being worked
+    /// with.
+    pub fn get_ref(&self) -> &UdpSocket {
+        self.inner.inner.borrow()
+    }
+
+    /// Returns the address of the peer the socket is connected to.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Returns a reference to the underlying codec wrapped by
+    /// `Framed`.
+    ///
+    /// Note that care should be taken to not tamper with the underlying codec
+    /// as it may corrupt the stream of frames otherwise being worked with.
+    pub fn codec(&self) -> &C {
+        &self.inner.codec
+    }
+
+    /// Returns a mutable reference to the underlying codec wrapped by
+    /// `UdpFramedConnectedRecv`.
+    ///
+    /// Note that care should be taken to not tamper with the underlying codec
+    /// as it may corrupt the stream of frames otherwise being worked with.
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.inner.codec
+    }
+
+    /// Returns a reference to the read buffer.
+    pub fn read_buffer(&self) -> &BytesMut {
+        &self.inner.state.buffer
+    }
+
+    /// Returns a mutable reference to the read buffer.
+    pub fn read_buffer_mut(&mut self) -> &mut BytesMut {
+        &mut self.inner.state.buffer
+    }
+
+    /// Consumes the `Framed`, returning its underlying I/O stream.
+    pub fn into_inner(self) -> T {
+        self.inner.inner
+    }
+}
+
+impl<T, C> Stream for UdpFramedConnectedRecv<T, C>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder,
+{
+    type Item = Result<C::Item, C::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project()
+            .inner
+            .poll_next(cx)
+            .map(|next| next.map(|res| res.map(|(frame, _)| frame)))
+    }
+}
+
+impl<T, C> fmt::Debug for UdpFramedConnectedRecv<T, C>
+where
+    T: Borrow<UdpSocket>,
+    C: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpFramedConnectedRecv")
+            .field("io", self.get_ref())
+            .field("codec", self.codec())
+            .field("peer", &self.peer)
+            .field("is_readable", &self.inner.state.is_readable)
+            .field("eof", &self.inner.state.eof)
+            .field("read_buffer", &self.read_buffer())
+            .finish()
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{
+    builder::UdpFramedBuilder,
+    codec::DatagramEncoder,
+    framed_impl::{UdpFramedImpl, WriteFrame},
+};
+
+use pin_project_lite::pin_project;
+use tokio::net::UdpSocket;
+
+use futures_sink::Sink;
+use std::{
+    borrow::Borrow,
+    fmt, io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pin_project! {
+    /// A [`Sink`] of frames encoded for a [`connect`]ed [`UdpSocket`].
+    ///
+    /// Every frame is sent to the connected peer, so the `Sink` takes plain items.
+    ///
+    /// [`Sink`]: futures_sink::Sink
+    /// [`connect`]: tokio::net::UdpSocket::connect
+    pub struct UdpFramedConnectedSend<T, C> {
+        #[pin]
+        inner: UdpFramedImpl<T, C, WriteFrame>,
+        peer: SocketAddr,
+    }
+}
+
+impl<T, C> UdpFramedConnectedSend<T, C>
+where
+    T: Borrow<UdpSocket>,
+{
+    /// Create a new `UdpFramedConnectedSend` backed by the given connected socket and
+    /// codec.
+    ///
+    /// Returns an error if the socket hasn't been connected. See [`UdpFramedBuilder`]
+    /// to configure buffer sizes and other options.
+    pub fn new(socket: T, codec: C) -> io::Result<UdpFramedConnectedSend<T, C>> {
+        UdpFramedBuilder::new().build_connected_send(socket, codec)
+    }
+
+    pub(crate) fn from_parts(
+        socket: T,
+        codec: C,
+        mut state: WriteFrame,
+    ) -> io::Result<UdpFramedConnectedSend<T, C>> {
+        let peer = socket.borrow().peer_addr()?;
+        state.connected = true;
+        Ok(Self {
+            inner: UdpFramedImpl {
+                inner: socket,
+                codec,
+                state,
+                current_meta: None,
+            },
+            peer,
+        })
+    }
+
+    /// Returns a reference to the underlying I/O stream wrapped by `Framed`.
+    ///
+    /// # Note
+    ///
+    /// Care should be taken to not tamper with the underlying stream of data
+    /// coming in as it may corrupt the stream of frames otherwise being worked
+    /// with.
+    pub fn get_ref(&self) -> &UdpSocket {
+        self.inner.inner.borrow()
+    }
+
+    /// Returns the address of the peer the socket is connected to.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Returns a reference to the underlying codec wrapped by
+    /// `Framed`.
+    ///
+    /// Note that care should be taken to not tamper with the underlying codec
+    /// as it may corrupt the stream of frames otherwise being worked with.
+    pub fn encoder(&self) -> &C {
+        &self.inner.codec
+    }
+
+    /// Returns a mutable reference to the underlying codec wrapped by
+    /// `UdpFramedConnectedSend`.
+    ///
+    /// Note that care should be taken to not tamper with the underlying codec
+    /// as it may corrupt the stream of frames otherwise being worked with.
+    pub fn encoder_mut(&mut self) -> &mut C {
+        &mut self.inner.codec
+    }
+
+    /// Consumes the `Framed`, returning its underlying I/O stream.
+    pub fn into_inner(self) -> T {
+        self.inner.inner
+    }
+}
+
+// This impl just defers to the underlying FramedImpl
+impl<T, I, U> Sink<I> for UdpFramedConnectedSend<T, U>
+where
+    T: Borrow<UdpSocket>,
+    U: DatagramEncoder<I>,
+    U::Error: From<io::Error>,
+{
+    type Error = U::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let pin = self.project();
+        pin.inner.start_send((item, *pin.peer))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+impl<T, C> fmt::Debug for UdpFramedConnectedSend<T, C>
+where
+    T: Borrow<UdpSocket>,
+    C: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpFramedConnectedSend")
+            .field("io", self.get_ref())
+            .field("codec", self.encoder())
+            .field("peer", &self.peer)
+            .field("buffer", &self.inner.state.buffer)
+            .field("send_queue", &self.inner.state.queue)
+            .finish()
+    }
+}
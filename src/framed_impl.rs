@@ -1,19 +1,29 @@
-use tokio_util::codec::{Decoder, Encoder};
+use crate::{
+    codec::{DatagramDecoder, DatagramEncoder},
+    error::{DatagramTooLarge, DatagramTruncated, PartialSend, TrailingBytes},
+    meta::{MetaItem, RecvMeta, SendMeta},
+    sys::RecvInfo,
+    trailing::TrailingData,
+};
 
 use pin_project_lite::pin_project;
-use tokio::{io::ReadBuf, net::UdpSocket};
+#[cfg(target_os = "linux")]
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
 use tokio_stream::Stream;
 
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 
 use futures_core::ready;
 use futures_sink::Sink;
 
 use std::{
     borrow::{Borrow, BorrowMut},
+    collections::VecDeque,
     io,
-    mem::MaybeUninit,
+    mem::{self, MaybeUninit},
     net::SocketAddr,
+    ops::Range,
     pin::Pin,
     task::{Context, Poll},
 };
@@ -29,15 +39,293 @@ pin_project! {
 }
 
 const INITIAL_CAPACITY: usize = 8 * 1024;
+/// The most retired read buffers kept around in zero-copy mode.
+const POOL_SIZE: usize = 4;
+/// In zero-copy mode, read buffers are allocated with room for this many datagrams of
+/// the largest size.
+const POOL_BUFFER_DATAGRAMS: usize = 4;
 
 pub(crate) struct ReadFrame {
     pub(crate) eof: bool,
     pub(crate) is_readable: bool,
     pub(crate) buffer: BytesMut,
+    /// When set, datagrams are received in batches instead of one at a time.
+    pub(crate) batch: Option<RecvBatch>,
+    /// UDP GRO has been enabled on the socket, so a single read may return several
+    /// coalesced datagrams.
+    pub(crate) gro: bool,
+    /// What to do with bytes the decoder leaves behind in a datagram.
+    pub(crate) trailing: TrailingData,
+    /// The most bytes that will be received for a single datagram, anything larger is
+    /// truncated by the kernel.
+    pub(crate) max_datagram_size: usize,
+    /// The kernel has been asked for the destination address and interface of each
+    /// datagram.
+    pub(crate) pktinfo: bool,
+    /// The kernel has been asked for the ECN codepoint of each datagram.
+    pub(crate) ecn: bool,
+    /// The kernel has been asked to timestamp each datagram on arrival.
+    pub(crate) timestamps: bool,
+    /// Split each datagram off into its own `BytesMut` and refill `buffer` from `pool`
+    /// instead of clearing it.
+    pub(crate) zero_copy: bool,
+    /// In zero-copy mode, the free space left after the last datagram.
+    pub(crate) spare: BytesMut,
+    /// In zero-copy mode, retired read buffers that may be reused once all the frames
+    /// sharing them have been dropped.
+    pub(crate) pool: Vec<BytesMut>,
+    /// The capacity fresh read buffers are allocated with in zero-copy mode, room for
+    /// a few datagrams of the largest size when `None`.
+    pub(crate) capacity: Option<usize>,
+    /// Skip errors the kernel reports for ICMP messages about earlier sends instead of
+    /// returning them.
+    pub(crate) ignore_icmp_errors: bool,
+}
+
+impl ReadFrame {
+    /// The number of datagrams received per syscall.
+    pub(crate) fn batch_size(&self) -> usize {
+        self.batch.as_ref().map_or(1, |batch| batch.batch_size())
+    }
+
+    /// Receive up to `batch_size` datagrams per syscall. The batched path is also
+    /// used for a `batch_size` of 1 with GRO since it knows how to split segments.
+    pub(crate) fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch = if batch_size > 1 || self.gro {
+            // a coalesced GRO read can be up to 64KiB no matter how large each segment is
+            let datagram_size = if self.gro {
+                self.max_datagram_size.max(INITIAL_RD_CAPACITY)
+            } else {
+                self.max_datagram_size
+            };
+            Some(RecvBatch::new(batch_size.max(1), datagram_size))
+        } else {
+            None
+        };
+    }
+
+    pub(crate) fn set_max_datagram_size(&mut self, max_datagram_size: usize) {
+        // batch slots are this size, there has to be room for at least one byte
+        self.max_datagram_size = max_datagram_size.max(1);
+        self.set_batch_size(self.batch_size());
+    }
+
+    pub(crate) fn set_gro(&mut self, socket: &UdpSocket, enabled: bool) -> io::Result<()> {
+        crate::sys::set_gro(socket, enabled)?;
+        self.gro = enabled;
+        self.set_batch_size(self.batch_size());
+        Ok(())
+    }
+
+    pub(crate) fn set_pktinfo(&mut self, socket: &UdpSocket, enabled: bool) -> io::Result<()> {
+        crate::sys::set_pktinfo(socket, enabled)?;
+        self.pktinfo = enabled;
+        Ok(())
+    }
+
+    pub(crate) fn set_ecn(&mut self, socket: &UdpSocket, enabled: bool) -> io::Result<()> {
+        crate::sys::set_recv_ecn(socket, enabled)?;
+        self.ecn = enabled;
+        Ok(())
+    }
+
+    pub(crate) fn set_timestamps(&mut self, socket: &UdpSocket, enabled: bool) -> io::Result<()> {
+        crate::sys::set_recv_timestamps(socket, enabled)?;
+        self.timestamps = enabled;
+        Ok(())
+    }
+
+    pub(crate) fn set_zero_copy(&mut self, enabled: bool) {
+        self.zero_copy = enabled;
+        if !enabled {
+            self.spare = BytesMut::new();
+            self.pool.clear();
+        }
+    }
+
+    /// Receive the next datagram into `buffer`, replacing whatever it held before.
+    ///
+    /// A truncated datagram is dropped, leaving `buffer` empty, and reported with
+    /// `RecvInfo::truncated`, see [`truncated_error`]. In zero-copy mode
+    /// `buffer` ends up holding the datagram and nothing else, the rest of the
+    /// allocation is kept in `spare` for the following datagram.
+    ///
+    /// [`truncated_error`]: ReadFrame::truncated_error
+    pub(crate) fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        socket: &UdpSocket,
+    ) -> Poll<io::Result<RecvInfo>> {
+        self.prepare_buffer();
+
+        let info = loop {
+            if let Some(batch) = self.batch.as_mut() {
+                // Hand out the rest of the last batch before receiving another one
+                match batch.pop() {
+                    Some((datagram, info)) => {
+                        self.buffer.extend_from_slice(datagram);
+                        break info;
+                    }
+                    None => match ready!(batch.poll_recv(cx, socket)) {
+                        Err(err) if self.ignore_icmp_errors && crate::sys::is_icmp_error(&err) => {}
+                        res => res?,
+                    },
+                }
+            } else {
+                // SAFETY: Convert `&mut [MaybeUnit<u8>]` to `&mut [u8]` because we will be
+                // writing to it via `poll_recv_msg` and therefore initializing the memory.
+                let buf =
+                    unsafe { &mut *(self.buffer.chunk_mut() as *mut _ as *mut [MaybeUninit<u8>]) };
+                let max = self.max_datagram_size.min(buf.len());
+                let (len, info) =
+                    match ready!(crate::sys::poll_recv_msg(socket, cx, &mut buf[..max])) {
+                        Err(err) if self.ignore_icmp_errors && crate::sys::is_icmp_error(&err) => {
+                            continue
+                        }
+                        res => res?,
+                    };
+                // SAFETY: `poll_recv_msg` initialized the first `len` bytes
+                unsafe { self.buffer.advance_mut(len) };
+                break info;
+            }
+        };
+
+        // the end of a truncated datagram is missing, don't try to decode it
+        if info.truncated {
+            self.buffer.clear();
+        } else if self.zero_copy {
+            let len = self.buffer.len();
+            self.spare = self.buffer.split_off(len);
+        }
+        Poll::Ready(Ok(info))
+    }
+
+    /// The error for a datagram from `addr` that `poll_recv` reported as truncated.
+    pub(crate) fn truncated_error(&self, addr: SocketAddr) -> io::Error {
+        let err = DatagramTruncated {
+            addr,
+            max_datagram_size: self.max_datagram_size,
+        };
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+
+    /// Make sure `buffer` is empty with room for a whole datagram.
+    fn prepare_buffer(&mut self) {
+        if !self.zero_copy {
+            self.buffer.clear();
+            self.buffer.reserve(self.max_datagram_size);
+            return;
+        }
+
+        // the last datagram was split off into its own `BytesMut`, which may still be
+        // shared with frames the codec froze, carry on in the space after it. `spare` is
+        // empty when nothing was received since, keep the buffer prepared last time
+        if self.spare.capacity() > 0 {
+            self.buffer = mem::take(&mut self.spare);
+        } else {
+            self.buffer.clear();
+        }
+        if self.buffer.capacity() >= self.max_datagram_size {
+            return;
+        }
+
+        let size = self.max_datagram_size;
+        let retired = mem::take(&mut self.buffer);
+        // a pooled buffer can be reused once every frame taken from it has been dropped
+        let reclaimed = self.pool.iter_mut().position(|buf| buf.try_reclaim(size));
+        let capacity = self
+            .capacity
+            .map_or(size * POOL_BUFFER_DATAGRAMS, |capacity| capacity.max(size));
+        self.buffer = match reclaimed {
+            Some(i) => self.pool.swap_remove(i),
+            None => BytesMut::with_capacity(capacity),
+        };
+        if self.pool.len() < POOL_SIZE && retired.capacity() > 0 {
+            self.pool.push(retired);
+        }
+    }
+}
+
+/// Storage for receiving several datagrams per readiness event.
+///
+/// On Linux this is filled with a single `recvmmsg` call, elsewhere it falls back
+/// to receiving one datagram at a time.
+pub(crate) struct RecvBatch {
+    buffer: Box<[u8]>,
+    datagram_size: usize,
+    received: VecDeque<(Range<usize>, RecvInfo)>,
+}
+
+impl RecvBatch {
+    pub(crate) fn new(batch_size: usize, datagram_size: usize) -> Self {
+        Self {
+            buffer: vec![0; batch_size * datagram_size].into_boxed_slice(),
+            datagram_size,
+            received: VecDeque::with_capacity(batch_size),
+        }
+    }
+
+    /// The number of datagrams received per call.
+    pub(crate) fn batch_size(&self) -> usize {
+        self.buffer.len() / self.datagram_size
+    }
+
+    /// Take the next received datagram that hasn't been decoded yet.
+    pub(crate) fn pop(&mut self) -> Option<(&[u8], RecvInfo)> {
+        let (range, info) = self.received.pop_front()?;
+        Some((&self.buffer[range], info))
+    }
+
+    /// Receive the next batch of datagrams, must only be called once all datagrams
+    /// from the previous batch have been taken with `pop`.
+    pub(crate) fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        socket: &UdpSocket,
+    ) -> Poll<io::Result<()>> {
+        debug_assert!(self.received.is_empty());
+
+        #[cfg(target_os = "linux")]
+        {
+            let (buffer, datagram_size, received) =
+                (&mut self.buffer, self.datagram_size, &mut self.received);
+            ready!(crate::sys::poll_io(socket, cx, Interest::READABLE, || {
+                crate::sys::recv_mmsg(socket, buffer, datagram_size, received)
+            }))?;
+            Poll::Ready(Ok(()))
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            // SAFETY: `[u8]` and `[MaybeUninit<u8>]` have the same layout and only
+            // initialized bytes are written to the buffer
+            let buf = unsafe {
+                &mut *(&mut self.buffer[..self.datagram_size] as *mut [u8]
+                    as *mut [MaybeUninit<u8>])
+            };
+            let (len, info) = ready!(crate::sys::poll_recv_msg(socket, cx, buf))?;
+            self.received.push_back((0..len, info));
+            Poll::Ready(Ok(()))
+        }
+    }
 }
 
 pub(crate) struct WriteFrame {
     pub(crate) buffer: BytesMut,
+    /// Encoded datagrams waiting to be sent, in the order they were given to `start_send`.
+    pub(crate) queue: VecDeque<(Bytes, SendMeta)>,
+    /// `poll_ready` will not accept new items until the queue is flushed once it holds
+    /// this many datagrams.
+    pub(crate) high_water_mark: usize,
+    /// Send runs of same-size datagrams to the same address as a single UDP GSO buffer.
+    pub(crate) gso: bool,
+    /// Datagrams larger than this are sent on their own rather than segmented, lowered
+    /// when the kernel rejects a segment as too large for the path MTU.
+    pub(crate) gso_max_segment: usize,
+    /// The socket is connected, datagrams are sent to its peer without an address.
+    pub(crate) connected: bool,
+    /// Encoded frames larger than this are rejected by `start_send`.
+    pub(crate) max_datagram_size: usize,
 }
 
 #[derive(Default)]
@@ -52,6 +340,18 @@ impl Default for ReadFrame {
             eof: false,
             is_readable: false,
             buffer: BytesMut::with_capacity(INITIAL_CAPACITY),
+            batch: None,
+            gro: false,
+            trailing: TrailingData::default(),
+            max_datagram_size: INITIAL_RD_CAPACITY,
+            pktinfo: false,
+            ecn: false,
+            timestamps: false,
+            zero_copy: false,
+            spare: BytesMut::new(),
+            pool: Vec::new(),
+            capacity: None,
+            ignore_icmp_errors: false,
         }
     }
 }
@@ -60,12 +360,21 @@ impl Default for WriteFrame {
     fn default() -> Self {
         Self {
             buffer: BytesMut::with_capacity(INITIAL_CAPACITY),
+            queue: VecDeque::new(),
+            high_water_mark: DEFAULT_HIGH_WATER_MARK,
+            gso: false,
+            gso_max_segment: usize::MAX,
+            connected: false,
+            max_datagram_size: DEFAULT_SEND_MAX_DATAGRAM_SIZE,
         }
     }
 }
 
 impl From<BytesMut> for ReadFrame {
     fn from(mut buffer: BytesMut) -> Self {
+        // every datagram starts with an empty buffer, there's no address to decode
+        // existing contents with
+        buffer.clear();
         let size = buffer.capacity();
         if size < INITIAL_CAPACITY {
             buffer.reserve(INITIAL_CAPACITY - size);
@@ -73,20 +382,23 @@ impl From<BytesMut> for ReadFrame {
 
         Self {
             buffer,
-            is_readable: size > 0,
-            eof: false,
+            ..ReadFrame::default()
         }
     }
 }
 
 impl From<BytesMut> for WriteFrame {
     fn from(mut buffer: BytesMut) -> Self {
+        buffer.clear();
         let size = buffer.capacity();
         if size < INITIAL_CAPACITY {
             buffer.reserve(INITIAL_CAPACITY - size);
         }
 
-        Self { buffer }
+        Self {
+            buffer,
+            ..WriteFrame::default()
+        }
     }
 }
 
@@ -118,82 +430,157 @@ pin_project! {
         pub(crate) inner: T,
         pub(crate) state: State,
         pub(crate) codec: U,
-        pub(crate) current_addr: Option<SocketAddr>,
-        pub(crate) out_addr: SocketAddr,
-        pub(crate) flushed: bool,
+        pub(crate) current_meta: Option<RecvMeta>,
+    }
+}
+
+impl<T, U, State> UdpFramedImpl<T, U, State> {
+    /// Replace the codec with `f(codec)`, keeping the socket and buffered state.
+    pub(crate) fn map_codec<V>(self, f: impl FnOnce(U) -> V) -> UdpFramedImpl<T, V, State> {
+        UdpFramedImpl {
+            inner: self.inner,
+            state: self.state,
+            codec: f(self.codec),
+            current_meta: self.current_meta,
+        }
     }
 }
 
 pub(crate) const INITIAL_RD_CAPACITY: usize = 64 * 1024;
 pub(crate) const INITIAL_WR_CAPACITY: usize = 8 * 1024;
+pub(crate) const DEFAULT_HIGH_WATER_MARK: usize = 64;
+/// The largest payload a UDP datagram over IPv4 can carry.
+pub(crate) const DEFAULT_SEND_MAX_DATAGRAM_SIZE: usize = 65_507;
 
 impl<T, C, R> Stream for UdpFramedImpl<T, C, R>
 where
     T: Borrow<UdpSocket>,
-    C: Decoder,
+    C: DatagramDecoder,
     R: BorrowMut<ReadFrame>,
 {
     type Item = Result<(C::Item, SocketAddr), C::Error>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_next_meta(cx)
+            .map(|next| next.map(|res| res.map(|(frame, meta)| (frame, meta.addr))))
+    }
+}
+
+impl<T, C, R> UdpFramedImpl<T, C, R>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder,
+    R: BorrowMut<ReadFrame>,
+{
+    /// Like `poll_next`, but yields everything known about the datagram a frame was
+    /// decoded from.
+    pub(crate) fn poll_next_meta(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<MetaItem<C>>> {
         let pin = self.project();
 
         let read_state: &mut ReadFrame = pin.state.borrow_mut();
-        read_state.buffer.reserve(INITIAL_RD_CAPACITY);
 
         loop {
             // Are there are still bytes left in the read buffer to decode?
             if read_state.is_readable {
-                if let Some(frame) = pin.codec.decode_eof(&mut read_state.buffer)? {
-                    let current_addr = pin
-                        .current_addr
-                        .expect("will always be set before this line is called");
-
-                    return Poll::Ready(Some(Ok((frame, current_addr))));
+                let current_meta = pin
+                    .current_meta
+                    .expect("will always be set before this line is called");
+                if let Some(frame) = pin
+                    .codec
+                    .decode_datagram(&mut read_state.buffer, &current_meta)?
+                {
+                    return Poll::Ready(Some(Ok((frame, current_meta))));
                 }
 
                 // if this line has been reached then decode has returned `None`.
                 read_state.is_readable = false;
+                if !read_state.buffer.is_empty() {
+                    let current_addr = current_meta.addr;
+
+                    match &mut read_state.trailing {
+                        TrailingData::Discard => {}
+                        TrailingData::Error => {
+                            let err = TrailingBytes {
+                                addr: current_addr,
+                                len: read_state.buffer.len(),
+                            };
+                            read_state.buffer.clear();
+                            let err = io::Error::new(io::ErrorKind::InvalidData, err);
+                            if let Some(err) = pin.codec.datagram_error(err, &current_meta) {
+                                return Poll::Ready(Some(Err(err)));
+                            }
+                        }
+                        TrailingData::Callback(f) => f(read_state.buffer.split(), current_addr),
+                    }
+                }
                 read_state.buffer.clear();
             }
 
-            // We're out of data. Try and fetch more data to decode
-            let addr = unsafe {
-                // Convert `&mut [MaybeUnit<u8>]` to `&mut [u8]` because we will be
-                // writing to it via `poll_recv_from` and therefore initializing the memory.
-                let buf =
-                    &mut *(read_state.buffer.chunk_mut() as *mut _ as *mut [MaybeUninit<u8>]);
-                let mut read = ReadBuf::uninit(buf);
-                let ptr = read.filled().as_ptr();
-                let res = ready!((*pin.inner).borrow().poll_recv_from(cx, &mut read));
-
-                assert_eq!(ptr, read.filled().as_ptr());
-                let addr = res?;
-                read_state.buffer.advance_mut(read.filled().len());
-                addr
-            };
-
-            *pin.current_addr = Some(addr);
+            let info = ready!(read_state.poll_recv(cx, (*pin.inner).borrow()))?;
+            if info.truncated {
+                let err = read_state.truncated_error(info.meta.addr);
+                match pin.codec.datagram_error(err, &info.meta) {
+                    Some(err) => return Poll::Ready(Some(Err(err))),
+                    None => continue,
+                }
+            }
+            *pin.current_meta = Some(info.meta);
             read_state.is_readable = true;
         }
     }
 }
 
+impl<T, C, W> UdpFramedImpl<T, C, W>
+where
+    T: Borrow<UdpSocket>,
+    W: BorrowMut<WriteFrame>,
+{
+    /// Like `start_send`, but sends according to everything in `meta` rather than just
+    /// a destination address.
+    pub(crate) fn start_send_meta<I>(
+        self: Pin<&mut Self>,
+        frame: I,
+        meta: SendMeta,
+    ) -> Result<(), C::Error>
+    where
+        C: DatagramEncoder<I>,
+    {
+        let pin = self.project();
+        let write_state: &mut WriteFrame = pin.state.borrow_mut();
+
+        pin.codec.encode_datagram(frame, &mut write_state.buffer)?;
+        if write_state.buffer.len() > write_state.max_datagram_size {
+            let err = DatagramTooLarge {
+                len: write_state.buffer.len(),
+                max_datagram_size: write_state.max_datagram_size,
+            };
+            write_state.buffer.clear();
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err).into());
+        }
+        // each item becomes its own datagram, so split it off the encode buffer
+        let datagram = write_state.buffer.split().freeze();
+        write_state.queue.push_back((datagram, meta));
+
+        Ok(())
+    }
+}
+
 impl<T, I, C, W> Sink<(I, SocketAddr)> for UdpFramedImpl<T, C, W>
 where
     T: Borrow<UdpSocket>,
-    C: Encoder<I>,
+    C: DatagramEncoder<I>,
     C::Error: From<io::Error>,
     W: BorrowMut<WriteFrame>,
 {
     type Error = C::Error;
 
     fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        if !self.flushed {
-            match self.poll_flush(cx)? {
-                Poll::Ready(()) => {}
-                Poll::Pending => return Poll::Pending,
-            }
+        let write_state: &WriteFrame = self.state.borrow();
+        if write_state.queue.len() >= write_state.high_water_mark {
+            ready!(self.poll_flush(cx))?;
         }
 
         Poll::Ready(Ok(()))
@@ -201,43 +588,101 @@ where
 
     fn start_send(self: Pin<&mut Self>, item: (I, SocketAddr)) -> Result<(), Self::Error> {
         let (frame, out_addr) = item;
+        self.start_send_meta(frame, SendMeta::new(out_addr))
+    }
 
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         let pin = self.project();
         let write_state: &mut WriteFrame = pin.state.borrow_mut();
 
-        pin.codec.encode(frame, &mut write_state.buffer)?;
-        *pin.out_addr = out_addr;
-        *pin.flushed = false;
-
-        Ok(())
-    }
+        let socket = (*pin.inner).borrow();
+        #[cfg(target_os = "linux")]
+        let mut sent = Vec::new();
+        // a batch failed, send the datagram at the front on its own to find out if
+        // the error is about it
+        #[cfg(target_os = "linux")]
+        let mut send_one = false;
+
+        while let Some((datagram, out_meta)) = write_state.queue.front() {
+            // several datagrams are ready, send as many as we can with one syscall
+            #[cfg(target_os = "linux")]
+            if write_state.queue.len() > 1 && !mem::take(&mut send_one) {
+                let (queue, gso, max_segment, connected) = (
+                    &write_state.queue,
+                    write_state.gso,
+                    write_state.gso_max_segment,
+                    write_state.connected,
+                );
+                let res = ready!(crate::sys::poll_io(socket, cx, Interest::WRITABLE, || {
+                    crate::sys::send_mmsg(socket, queue, gso, max_segment, connected, &mut sent)
+                }));
+                match res {
+                    // the device can't do segmentation offload, go back to plain sends
+                    Err(err) if gso && err.raw_os_error() == Some(libc::EIO) => {
+                        write_state.gso = false;
+                        continue;
+                    }
+                    // the segments of the first message don't fit in the path MTU, send
+                    // datagrams of this size and larger on their own from now on
+                    Err(err)
+                        if gso
+                            && matches!(
+                                err.raw_os_error(),
+                                Some(libc::EINVAL) | Some(libc::EMSGSIZE)
+                            )
+                            && crate::sys::gso_run(queue, 0, max_segment) > 1 =>
+                    {
+                        write_state.gso_max_segment = queue[0].0.len() - 1;
+                        continue;
+                    }
+                    Err(_) => {
+                        send_one = true;
+                        continue;
+                    }
+                    Ok(()) => {}
+                }
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        let pin = self.project();
-        if *pin.flushed {
-            return Poll::Ready(Ok(()));
-        }
+                // anything the kernel didn't take stays queued for the next call
+                for (count, n) in sent.drain(..) {
+                    let addr = write_state.queue[0].1.addr;
+                    let len = write_state
+                        .queue
+                        .drain(..count)
+                        .map(|(datagram, _)| datagram.len())
+                        .sum::<usize>();
+                    if n != len {
+                        return Poll::Ready(Err(incomplete_send(addr, len, n).into()));
+                    }
+                }
+                continue;
+            }
 
-        let write_state: &mut WriteFrame = pin.state.borrow_mut();
-        let n = ready!((*pin.inner)
-            .borrow()
-            .poll_send_to(cx, &write_state.buffer, *pin.out_addr))?;
+            let res = if out_meta.needs_cmsg() {
+                ready!(crate::sys::poll_send_msg(
+                    socket,
+                    cx,
+                    datagram,
+                    out_meta,
+                    write_state.connected
+                ))
+            } else if write_state.connected {
+                ready!(socket.poll_send(cx, datagram))
+            } else {
+                ready!(socket.poll_send_to(cx, datagram, out_meta.addr))
+            };
 
-        let wrote_all = n == write_state.buffer.len();
-        write_state.buffer.clear();
-        *pin.flushed = true;
+            let (len, addr) = (datagram.len(), out_meta.addr);
+            // a datagram the kernel rejected would be rejected again, drop it so the
+            // rest of the queue can still be sent
+            write_state.queue.pop_front();
+            let n = res?;
 
-        let res = if wrote_all {
-            Ok(())
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                "failed to write entire datagram to socket",
-            )
-            .into())
-        };
+            if n != len {
+                return Poll::Ready(Err(incomplete_send(addr, len, n).into()));
+            }
+        }
 
-        Poll::Ready(res)
+        Poll::Ready(Ok(()))
     }
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -245,3 +690,7 @@ where
         Poll::Ready(Ok(()))
     }
 }
+
+fn incomplete_send(addr: SocketAddr, len: usize, sent: usize) -> io::Error {
+    io::Error::other(PartialSend { addr, len, sent })
+}
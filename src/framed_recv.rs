@@ -1,6 +1,10 @@
-use tokio_util::codec::Decoder;
-
-use crate::framed_impl::{ReadFrame, UdpFramedImpl};
+use crate::{
+    builder::UdpFramedBuilder,
+    codec::{DatagramDecoder, Resilient},
+    framed_impl::{ReadFrame, UdpFramedImpl},
+    meta::{MetaItem, WithMeta},
+    trailing::TrailingData,
+};
 
 use pin_project_lite::pin_project;
 use tokio::net::UdpSocket;
@@ -9,8 +13,8 @@ use tokio_stream::Stream;
 use bytes::BytesMut;
 use std::{
     borrow::Borrow,
-    fmt,
-    net::SocketAddr,
+    fmt, io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     pin::Pin,
     task::{Context, Poll},
 };
@@ -32,19 +36,19 @@ where
 {
     /// Create a new `UdpFramed` backed by the given socket and codec.
     ///
-    /// See struct level documentation for more details.
+    /// See struct level documentation for more details, or [`UdpFramedBuilder`] to
+    /// configure buffer sizes and other options.
     pub fn new(socket: T, codec: C) -> UdpFramedRecv<T, C> {
+        UdpFramedBuilder::new().build_recv(socket, codec)
+    }
+
+    pub(crate) fn from_parts(socket: T, codec: C, state: ReadFrame) -> UdpFramedRecv<T, C> {
         Self {
             inner: UdpFramedImpl {
-                codec,
-                state: ReadFrame {
-                    buffer: BytesMut::with_capacity(crate::framed_impl::INITIAL_RD_CAPACITY),
-                    ..ReadFrame::default()
-                },
                 inner: socket,
-                current_addr: None,
-                out_addr: ([0, 0, 0, 0], 0).into(),
-                flushed: true,
+                codec,
+                state,
+                current_meta: None,
             },
         }
     }
@@ -60,6 +64,10 @@ where
         self.inner.inner.borrow()
     }
 
+    pub(crate) fn socket(&self) -> &T {
+        &self.inner.inner
+    }
+
     /// Returns a reference to the underlying codec wrapped by
     /// `Framed`.
     ///
@@ -88,6 +96,262 @@ where
         &mut self.inner.state.buffer
     }
 
+    /// Returns the number of datagrams received per syscall, `1` unless batching
+    /// has been enabled with [`set_recv_batch_size`].
+    ///
+    /// [`set_recv_batch_size`]: Self::set_recv_batch_size
+    pub fn recv_batch_size(&self) -> usize {
+        self.inner.state.batch_size()
+    }
+
+    /// Receive up to `batch_size` datagrams per readiness event.
+    ///
+    /// On Linux this uses a single `recvmmsg` call per batch, on other platforms
+    /// datagrams are still received one at a time. Each datagram keeps its own
+    /// source address and is decoded on its own. A `batch_size` of `0` or `1`
+    /// disables batching.
+    ///
+    /// The batch buffer is allocated up front with one slot per datagram, each
+    /// [`recv_max_datagram_size`] bytes long, or at least 64 KiB when GRO is enabled.
+    /// With the default 64 KiB maximum a batch of 64 takes 4 MiB, lower the maximum
+    /// with [`set_recv_max_datagram_size`] if datagrams are known to be smaller.
+    ///
+    /// This should be set before receiving, any datagrams left over from a previous
+    /// batch are dropped.
+    ///
+    /// [`recv_max_datagram_size`]: Self::recv_max_datagram_size
+    /// [`set_recv_max_datagram_size`]: Self::set_recv_max_datagram_size
+    pub fn set_recv_batch_size(&mut self, batch_size: usize) {
+        self.inner.state.set_batch_size(batch_size);
+    }
+
+    /// Returns `true` if UDP generic receive offload is enabled.
+    pub fn recv_gro(&self) -> bool {
+        self.inner.state.gro
+    }
+
+    /// Enable or disable UDP generic receive offload (`UDP_GRO`) on the socket.
+    ///
+    /// With GRO the kernel may coalesce several datagrams from the same sender into
+    /// a single read. They are split back apart at the segment size the kernel
+    /// reports, and each one is decoded on its own and tagged with the sender's
+    /// address. Returns an error if the socket option can't be set, GRO is only
+    /// available on Linux.
+    pub fn set_recv_gro(&mut self, enabled: bool) -> io::Result<()> {
+        let socket = self.inner.inner.borrow();
+        self.inner.state.set_gro(socket, enabled)
+    }
+
+    /// Returns `true` if the destination address and interface of received datagrams
+    /// are reported.
+    pub fn recv_pktinfo(&self) -> bool {
+        self.inner.state.pktinfo
+    }
+
+    /// Enable or disable reporting the local destination address and interface index
+    /// of received datagrams (`IP_PKTINFO` / `IPV6_RECVPKTINFO`).
+    ///
+    /// These show up in the [`RecvMeta`] yielded by [`with_meta`]. Returns an error
+    /// if the socket option can't be set, this is only available on Linux and Apple
+    /// platforms.
+    ///
+    /// [`RecvMeta`]: crate::RecvMeta
+    /// [`with_meta`]: UdpFramedRecv::with_meta
+    pub fn set_recv_pktinfo(&mut self, enabled: bool) -> io::Result<()> {
+        let socket = self.inner.inner.borrow();
+        self.inner.state.set_pktinfo(socket, enabled)
+    }
+
+    /// Returns `true` if the ECN codepoint of received datagrams is reported.
+    pub fn recv_ecn(&self) -> bool {
+        self.inner.state.ecn
+    }
+
+    /// Enable or disable reporting the ECN codepoint of received datagrams
+    /// (`IP_RECVTOS` / `IPV6_RECVTCLASS`).
+    ///
+    /// The codepoint shows up in the [`RecvMeta`] yielded by [`with_meta`]. Returns an
+    /// error if the socket option can't be set, this is only available on Linux.
+    ///
+    /// [`RecvMeta`]: crate::RecvMeta
+    /// [`with_meta`]: UdpFramedRecv::with_meta
+    pub fn set_recv_ecn(&mut self, enabled: bool) -> io::Result<()> {
+        let socket = self.inner.inner.borrow();
+        self.inner.state.set_ecn(socket, enabled)
+    }
+
+    /// Returns `true` if received datagrams are timestamped by the kernel.
+    pub fn recv_timestamps(&self) -> bool {
+        self.inner.state.timestamps
+    }
+
+    /// Enable or disable kernel timestamps for received datagrams (`SO_TIMESTAMPNS`).
+    ///
+    /// The time each datagram arrived shows up in the [`RecvMeta`] yielded by
+    /// [`with_meta`], datagrams coalesced by GRO share a timestamp. Returns an error if
+    /// the socket option can't be set, this is only available on Linux.
+    ///
+    /// [`RecvMeta`]: crate::RecvMeta
+    /// [`with_meta`]: UdpFramedRecv::with_meta
+    pub fn set_recv_timestamps(&mut self, enabled: bool) -> io::Result<()> {
+        let socket = self.inner.inner.borrow();
+        self.inner.state.set_timestamps(socket, enabled)
+    }
+
+    /// Returns `true` if each datagram is split off into its own buffer before
+    /// decoding.
+    pub fn recv_zero_copy(&self) -> bool {
+        self.inner.state.zero_copy
+    }
+
+    /// Split each received datagram off the read buffer into its own `BytesMut`
+    /// before decoding it.
+    ///
+    /// A codec can then `freeze` the datagram, or `split_to` parts of it, and yield
+    /// `Bytes` without copying. Instead of clearing the read buffer for the next
+    /// datagram it is refilled from a small pool of buffers whose frames have all been
+    /// dropped. Datagrams received in batches are still copied once out of the batch
+    /// buffer. This should be set before receiving.
+    pub fn set_recv_zero_copy(&mut self, enabled: bool) {
+        self.inner.state.set_zero_copy(enabled);
+    }
+
+    /// Returns `true` if receive errors caused by ICMP messages are skipped.
+    pub fn recv_ignore_icmp_errors(&self) -> bool {
+        self.inner.state.ignore_icmp_errors
+    }
+
+    /// Skip receive errors caused by ICMP messages about earlier sends.
+    ///
+    /// After a datagram is sent to a closed port or an unreachable host, the kernel
+    /// may report the ICMP error it got back on the next receive, as
+    /// `ConnectionRefused`, `HostUnreachable` or `NetworkUnreachable` (`ConnectionReset`
+    /// on Windows). On Linux this happens for connected sockets and sockets with
+    /// `IP_RECVERR` set. These errors say nothing about the datagrams still to be
+    /// received, so with this enabled they are dropped and receiving carries on.
+    pub fn set_recv_ignore_icmp_errors(&mut self, enabled: bool) {
+        self.inner.state.ignore_icmp_errors = enabled;
+    }
+
+    /// Wrap this in a [`WithMeta`] whose `Stream` yields a [`RecvMeta`] with each frame
+    /// instead of just the source address.
+    ///
+    /// [`WithMeta`]: crate::WithMeta
+    /// [`RecvMeta`]: crate::RecvMeta
+    pub fn with_meta(self) -> WithMeta<Self> {
+        WithMeta::new(self)
+    }
+
+    /// Join the IPv4 multicast `group` on the interface with address `interface`, or
+    /// `Ipv4Addr::UNSPECIFIED` to let the kernel pick one.
+    ///
+    /// Enable [`set_recv_pktinfo`] to see which group each frame was sent to in
+    /// [`RecvMeta::dst_ip`].
+    ///
+    /// [`set_recv_pktinfo`]: UdpFramedRecv::set_recv_pktinfo
+    /// [`RecvMeta::dst_ip`]: crate::RecvMeta::dst_ip
+    pub fn join_multicast_v4(&self, group: Ipv4Addr, interface: Ipv4Addr) -> io::Result<()> {
+        self.get_ref().join_multicast_v4(group, interface)
+    }
+
+    /// Leave an IPv4 multicast group joined with [`join_multicast_v4`].
+    ///
+    /// [`join_multicast_v4`]: UdpFramedRecv::join_multicast_v4
+    pub fn leave_multicast_v4(&self, group: Ipv4Addr, interface: Ipv4Addr) -> io::Result<()> {
+        self.get_ref().leave_multicast_v4(group, interface)
+    }
+
+    /// Join the IPv6 multicast `group` on the interface with index `ifindex`, or 0 to
+    /// let the kernel pick one.
+    pub fn join_multicast_v6(&self, group: &Ipv6Addr, ifindex: u32) -> io::Result<()> {
+        self.get_ref().join_multicast_v6(group, ifindex)
+    }
+
+    /// Leave an IPv6 multicast group joined with [`join_multicast_v6`].
+    ///
+    /// [`join_multicast_v6`]: UdpFramedRecv::join_multicast_v6
+    pub fn leave_multicast_v6(&self, group: &Ipv6Addr, ifindex: u32) -> io::Result<()> {
+        self.get_ref().leave_multicast_v6(group, ifindex)
+    }
+
+    /// Join the IPv4 multicast `group` for what `source` sends to it only, on the
+    /// interface with address `interface` (source-specific multicast).
+    ///
+    /// This uses `IP_ADD_SOURCE_MEMBERSHIP`, which is available on Linux, Android,
+    /// FreeBSD and Apple platforms.
+    pub fn join_source_multicast_v4(
+        &self,
+        group: Ipv4Addr,
+        source: Ipv4Addr,
+        interface: Ipv4Addr,
+    ) -> io::Result<()> {
+        crate::sys::set_source_membership_v4(self.get_ref(), group, source, interface, true)
+    }
+
+    /// Leave a source-specific IPv4 multicast group joined with
+    /// [`join_source_multicast_v4`].
+    ///
+    /// [`join_source_multicast_v4`]: UdpFramedRecv::join_source_multicast_v4
+    pub fn leave_source_multicast_v4(
+        &self,
+        group: Ipv4Addr,
+        source: Ipv4Addr,
+        interface: Ipv4Addr,
+    ) -> io::Result<()> {
+        crate::sys::set_source_membership_v4(self.get_ref(), group, source, interface, false)
+    }
+
+    /// Skip datagrams the codec fails to decode instead of returning the error from
+    /// the `Stream`.
+    ///
+    /// The error and the address of the sender are handed to `on_error`, whatever is
+    /// left of the datagram is dropped and the `Stream` carries on with the next one.
+    /// Truncated datagrams and trailing bytes rejected by [`TrailingData::Error`] are
+    /// skipped the same way. Socket errors are still returned. See [`Resilient`].
+    ///
+    /// [`Resilient`]: crate::Resilient
+    /// [`TrailingData::Error`]: crate::TrailingData::Error
+    pub fn resilient<F>(self, on_error: F) -> UdpFramedRecv<T, Resilient<C, F>>
+    where
+        C: DatagramDecoder,
+        F: FnMut(C::Error, SocketAddr),
+    {
+        UdpFramedRecv {
+            inner: self
+                .inner
+                .map_codec(|codec| Resilient::new(codec, on_error)),
+        }
+    }
+
+    /// Returns the most bytes that will be received for a single datagram.
+    pub fn recv_max_datagram_size(&self) -> usize {
+        self.inner.state.max_datagram_size
+    }
+
+    /// Sets the most bytes that will be received for a single datagram, 64KiB by default.
+    ///
+    /// The kernel truncates datagrams larger than this. A truncated datagram is
+    /// dropped without being decoded and the `Stream` yields an `io::Error` of kind
+    /// `InvalidData` wrapping a [`DatagramTruncated`] instead. Truncation can't be
+    /// detected on platforms without `recvmsg`. A size of 0 is raised to 1.
+    ///
+    /// [`DatagramTruncated`]: crate::DatagramTruncated
+    pub fn set_recv_max_datagram_size(&mut self, max_datagram_size: usize) {
+        self.inner.state.set_max_datagram_size(max_datagram_size);
+    }
+
+    /// Returns the policy for bytes left over in a datagram after decoding.
+    pub fn trailing_data(&self) -> &TrailingData {
+        &self.inner.state.trailing
+    }
+
+    /// Sets what to do with bytes the decoder leaves behind in a datagram.
+    ///
+    /// By default they are discarded, see [`TrailingData`] for the alternatives.
+    pub fn set_trailing_data(&mut self, policy: TrailingData) {
+        self.inner.state.trailing = policy;
+    }
+
     /// Consumes the `Framed`, returning its underlying I/O stream.
     pub fn into_inner(self) -> T {
         self.inner.inner
@@ -97,7 +361,7 @@ where
 impl<T, C> Stream for UdpFramedRecv<T, C>
 where
     T: Borrow<UdpSocket>,
-    C: Decoder,
+    C: DatagramDecoder,
 {
     type Item = Result<(C::Item, SocketAddr), C::Error>;
 
@@ -106,6 +370,19 @@ where
     }
 }
 
+impl<T, C> UdpFramedRecv<T, C>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder,
+{
+    pub(crate) fn poll_next_meta(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<MetaItem<C>>> {
+        self.project().inner.poll_next_meta(cx)
+    }
+}
+
 impl<T, C> fmt::Debug for UdpFramedRecv<T, C>
 where
     T: Borrow<UdpSocket>,
@@ -115,8 +392,17 @@ where
         f.debug_struct("UdpFramedRecv")
             .field("io", self.get_ref())
             .field("codec", self.codec())
-            .field("current_addr", &self.inner.current_addr)
+            .field("current_meta", &self.inner.current_meta)
             .field("is_readable", &self.inner.state.is_readable)
+            .field("recv_batch_size", &self.recv_batch_size())
+            .field("recv_gro", &self.recv_gro())
+            .field("recv_max_datagram_size", &self.recv_max_datagram_size())
+            .field("recv_pktinfo", &self.recv_pktinfo())
+            .field("recv_ecn", &self.recv_ecn())
+            .field("recv_timestamps", &self.recv_timestamps())
+            .field("recv_zero_copy", &self.recv_zero_copy())
+            .field("recv_ignore_icmp_errors", &self.recv_ignore_icmp_errors())
+            .field("trailing_data", self.trailing_data())
             .field("eof", &self.inner.state.eof)
             .field("read_buffer", &self.read_buffer())
             .finish()
@@ -1,16 +1,18 @@
-use tokio_util::codec::Encoder;
-
-use crate::framed_impl::{UdpFramedImpl, WriteFrame};
+use crate::{
+    builder::UdpFramedBuilder,
+    codec::DatagramEncoder,
+    framed_impl::{UdpFramedImpl, WriteFrame},
+    meta::{SendMeta, WithMeta},
+};
 
 use pin_project_lite::pin_project;
 use tokio::net::UdpSocket;
 
-use bytes::BytesMut;
 use futures_sink::Sink;
 use std::{
     borrow::Borrow,
     fmt, io,
-    net::SocketAddr,
+    net::{Ipv4Addr, SocketAddr},
     pin::Pin,
     task::{Context, Poll},
 };
@@ -31,18 +33,19 @@ where
 {
     /// Create a new `UdpFramed` backed by the given socket and codec.
     ///
-    /// See struct level documentation for more details.
+    /// See struct level documentation for more details, or [`UdpFramedBuilder`] to
+    /// configure buffer sizes and other options.
     pub fn new(socket: T, codec: C) -> UdpFramedSend<T, C> {
+        UdpFramedBuilder::new().build_send(socket, codec)
+    }
+
+    pub(crate) fn from_parts(socket: T, codec: C, state: WriteFrame) -> UdpFramedSend<T, C> {
         Self {
             inner: UdpFramedImpl {
-                codec,
-                state: WriteFrame {
-                    buffer: BytesMut::with_capacity(crate::framed_impl::INITIAL_WR_CAPACITY),
-                },
                 inner: socket,
-                current_addr: None,
-                out_addr: ([0, 0, 0, 0], 0).into(),
-                flushed: true,
+                codec,
+                state,
+                current_meta: None,
             },
         }
     }
@@ -76,17 +79,133 @@ where
         &mut self.inner.codec
     }
 
+    /// Returns the number of datagrams that may be queued before `poll_ready`
+    /// waits for them to be flushed.
+    pub fn send_high_water_mark(&self) -> usize {
+        self.inner.state.high_water_mark
+    }
+
+    /// Sets the number of datagrams that may be queued before `poll_ready`
+    /// waits for them to be flushed.
+    ///
+    /// Every item passed to `start_send` is encoded into its own datagram and sent
+    /// to its own address. A value of `0` or `1` flushes before accepting each item.
+    /// A datagram the kernel refuses to send, for example to an invalid address, is
+    /// dropped from the queue and its error returned by `poll_flush`, flushing again
+    /// sends the rest.
+    pub fn set_send_high_water_mark(&mut self, hwm: usize) {
+        self.inner.state.high_water_mark = hwm;
+    }
+
+    /// Returns the most bytes allowed in a single encoded datagram.
+    pub fn send_max_datagram_size(&self) -> usize {
+        self.inner.state.max_datagram_size
+    }
+
+    /// Sets the most bytes allowed in a single encoded datagram, 65507 by default.
+    ///
+    /// Larger frames are rejected by `start_send` with an `io::Error` of kind
+    /// `InvalidInput` wrapping a [`DatagramTooLarge`], converted into the codec's
+    /// error type. Nothing is sent for them.
+    ///
+    /// [`DatagramTooLarge`]: crate::DatagramTooLarge
+    pub fn set_send_max_datagram_size(&mut self, max_datagram_size: usize) {
+        self.inner.state.max_datagram_size = max_datagram_size;
+    }
+
+    /// Returns `true` if runs of queued datagrams are sent using UDP generic
+    /// segmentation offload.
+    pub fn send_segmentation_offload(&self) -> bool {
+        self.inner.state.gso
+    }
+
+    /// Enable or disable UDP generic segmentation offload (`UDP_SEGMENT`) for sends.
+    ///
+    /// When enabled, consecutive queued datagrams going to the same address with the
+    /// same encoded size are handed to the kernel as one buffer, which is split back
+    /// into the individual datagrams on the way out. Support is checked at runtime,
+    /// this returns `false` and keeps sending one datagram at a time if the platform
+    /// or kernel can't do segmentation offload. It is also turned off again if the
+    /// device can't segment a send, and datagrams too large for the path MTU to be
+    /// segmented are sent on their own.
+    pub fn set_send_segmentation_offload(&mut self, enabled: bool) -> bool {
+        let enabled = enabled && crate::sys::gso_supported(self.get_ref());
+
+        self.inner.state.gso = enabled;
+        self.inner.state.gso_max_segment = usize::MAX;
+        enabled
+    }
+
+    /// Set the time-to-live of IPv4 multicast datagrams, how many routers they may
+    /// cross. The kernel default is 1, which keeps them on the local network.
+    pub fn set_multicast_ttl_v4(&self, ttl: u32) -> io::Result<()> {
+        self.get_ref().set_multicast_ttl_v4(ttl)
+    }
+
+    /// Set the hop limit of IPv6 multicast datagrams, the IPv6 counterpart of
+    /// [`set_multicast_ttl_v4`].
+    ///
+    /// [`set_multicast_ttl_v4`]: UdpFramedSend::set_multicast_ttl_v4
+    pub fn set_multicast_hops_v6(&self, hops: u32) -> io::Result<()> {
+        crate::sys::set_multicast_hops_v6(self.get_ref(), hops)
+    }
+
+    /// Set whether IPv4 multicast datagrams are looped back to sockets on this host
+    /// that joined the group. The kernel default is `true`.
+    pub fn set_multicast_loop_v4(&self, enabled: bool) -> io::Result<()> {
+        self.get_ref().set_multicast_loop_v4(enabled)
+    }
+
+    /// Set whether IPv6 multicast datagrams are looped back to sockets on this host
+    /// that joined the group. The kernel default is `true`.
+    pub fn set_multicast_loop_v6(&self, enabled: bool) -> io::Result<()> {
+        self.get_ref().set_multicast_loop_v6(enabled)
+    }
+
+    /// Send IPv4 multicast datagrams out of the interface with address `interface`
+    /// instead of the one the routing table picks.
+    pub fn set_multicast_interface_v4(&self, interface: Ipv4Addr) -> io::Result<()> {
+        crate::sys::set_multicast_if_v4(self.get_ref(), interface)
+    }
+
+    /// Send IPv6 multicast datagrams out of the interface with index `ifindex` instead
+    /// of the one the routing table picks.
+    pub fn set_multicast_interface_v6(&self, ifindex: u32) -> io::Result<()> {
+        crate::sys::set_multicast_if_v6(self.get_ref(), ifindex)
+    }
+
     /// Consumes the `Framed`, returning its underlying I/O stream.
     pub fn into_inner(self) -> T {
         self.inner.inner
     }
+
+    /// Wrap this in a [`WithMeta`] whose `Sink` takes a [`SendMeta`] instead of just
+    /// the destination address, to pick the source address or interface of each
+    /// datagram.
+    ///
+    /// [`WithMeta`]: crate::WithMeta
+    /// [`SendMeta`]: crate::SendMeta
+    pub fn with_meta(self) -> WithMeta<Self> {
+        WithMeta::new(self)
+    }
+
+    pub(crate) fn start_send_meta<I>(
+        self: Pin<&mut Self>,
+        frame: I,
+        meta: SendMeta,
+    ) -> Result<(), C::Error>
+    where
+        C: DatagramEncoder<I>,
+    {
+        self.project().inner.start_send_meta(frame, meta)
+    }
 }
 
 // This impl just defers to the underlying FramedImpl
 impl<T, I, U> Sink<(I, SocketAddr)> for UdpFramedSend<T, U>
 where
     T: Borrow<UdpSocket>,
-    U: Encoder<I>,
+    U: DatagramEncoder<I>,
     U::Error: From<io::Error>,
 {
     type Error = U::Error;
@@ -118,6 +237,13 @@ where
             .field("io", self.get_ref())
             .field("codec", self.encoder())
             .field("buffer", &self.inner.state.buffer)
+            .field("send_queue", &self.inner.state.queue)
+            .field("send_high_water_mark", &self.send_high_water_mark())
+            .field("send_max_datagram_size", &self.send_max_datagram_size())
+            .field(
+                "send_segmentation_offload",
+                &self.send_segmentation_offload(),
+            )
             .finish()
     }
 }
@@ -5,15 +5,29 @@
 //! - All `UdpFramed` types take a `Borrow<UdpSocket>` so you can pass an `Arc<UdpSocket>` or `&UdpSocket`
 //! - There are `UpdFramedRecv` and `UdpFramedSend` types for specifically `send` and `recv` in `Sink`/`Stream`
 //! - Because of `Borrow<UdpSocket>` you can't use `get_mut` anymore
+//! - `UdpFramedConnected`, `UdpFramedConnectedRecv` and `UdpFramedConnectedSend` work on a
+//!   `connect`ed socket and drop the `SocketAddr` from the `Sink` and `Stream` items
+//! - `UdpDatagrams` is a `Stream` of raw datagrams as `Bytes`, without a codec
+//! - `UdpFramedMux` splits what one socket receives into a `Stream` and `Sink` per peer
+//! - `UdpClient` matches responses to requests by a correlation id, with timeouts and retries
+//! - `Discovery` probes a broadcast or multicast address and collects who responds
+//! - `UdpServer`, behind the `server` feature, runs a handler for every request it receives
+//! - `Reliable`, behind the `reliable` feature, adds acknowledgements, retransmission and
+//!   ordering for one peer
 //!
 //! The main benefit can be easily explained in an example:
 //!
 //! ```rust
-//! # use std::sync::Arc;
+//! # use std::{io, sync::Arc};
+//! # use bytes::{BufMut, BytesMut};
+//! # use futures::SinkExt;
 //! # use tokio::net::UdpSocket;
+//! # use tokio_stream::StreamExt;
 //! use tokio_util::codec::{Decoder, Encoder, LinesCodec};
 //! use tokio_udp_framed::UdpFramed;
 //!
+//! # #[tokio::main]
+//! # async fn main() -> io::Result<()> {
 //! let a_soc = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
 //! let b_soc = a_soc.clone();
 //!
@@ -37,6 +51,8 @@
 //! assert_eq!(b.next().await.unwrap().unwrap(), ("4".to_string(), a_addr));
 //! assert_eq!(b.next().await.unwrap().unwrap(), ("5".to_string(), a_addr));
 //! assert_eq!(b.next().await.unwrap().unwrap(), ("6".to_string(), a_addr));
+//! # Ok(())
+//! # }
 //!
 //! pub struct ByteCodec;
 //!
@@ -60,11 +76,46 @@
 //!     }
 //! }
 //! ```
+mod builder;
+mod client;
+mod codec;
+mod datagrams;
+mod discovery;
+mod error;
 mod frame;
+mod framed_connected;
+mod framed_connected_recv;
+mod framed_connected_send;
 mod framed_impl;
 mod framed_recv;
 mod framed_send;
+mod meta;
+mod mux;
+#[cfg(feature = "reliable")]
+mod reliable;
+#[cfg(feature = "server")]
+mod server;
+mod sys;
+mod trailing;
 
+pub use builder::UdpFramedBuilder;
+pub use client::{Correlate, UdpClient};
+pub use codec::{DatagramDecoder, DatagramEncoder, Resilient, TypedErrors};
+pub use datagrams::UdpDatagrams;
+pub use discovery::Discovery;
+pub use error::{DatagramTooLarge, DatagramTruncated, PartialSend, TrailingBytes, UdpFramedError};
 pub use frame::UdpFramed;
+pub use framed_connected::UdpFramedConnected;
+pub use framed_connected_recv::UdpFramedConnectedRecv;
+pub use framed_connected_send::UdpFramedConnectedSend;
 pub use framed_recv::UdpFramedRecv;
 pub use framed_send::UdpFramedSend;
+pub use meta::{EcnCodepoint, RecvMeta, SendMeta, WithMeta};
+pub use mux::{PeerSink, PeerStream, UdpFramedMux};
+#[cfg(feature = "reliable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "reliable")))]
+pub use reliable::Reliable;
+#[cfg(feature = "server")]
+#[cfg_attr(docsrs, doc(cfg(feature = "server")))]
+pub use server::{Handler, UdpServer};
+pub use trailing::TrailingData;
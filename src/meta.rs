@@ -0,0 +1,308 @@
+use crate::{
+    codec::{DatagramDecoder, DatagramEncoder},
+    datagrams::UdpDatagrams,
+    frame::UdpFramed,
+    framed_recv::UdpFramedRecv,
+    framed_send::UdpFramedSend,
+};
+
+use pin_project_lite::pin_project;
+use tokio::net::UdpSocket;
+use tokio_stream::Stream;
+
+use bytes::Bytes;
+use futures_sink::Sink;
+use std::{
+    borrow::Borrow,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+/// What is known about a received datagram besides its contents.
+///
+/// Fields other than `addr` are only filled in when the matching socket option has
+/// been enabled and the platform reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RecvMeta {
+    /// The address the datagram came from.
+    pub addr: SocketAddr,
+    /// The destination address in the datagram's header, see `set_recv_pktinfo`. This
+    /// is the group or broadcast address for a multicast or broadcast datagram rather
+    /// than an address of this host.
+    pub dst_ip: Option<IpAddr>,
+    /// The index of the interface the datagram arrived on, see `set_recv_pktinfo`.
+    pub ifindex: Option<u32>,
+    /// The ECN codepoint the datagram was marked with, see `set_recv_ecn`. `None`
+    /// for datagrams that aren't ECN-capable.
+    pub ecn: Option<EcnCodepoint>,
+    /// When the kernel received the datagram, see `set_recv_timestamps`.
+    pub timestamp: Option<SystemTime>,
+    /// The size of the segments the datagram arrived coalesced with by generic receive
+    /// offload, see `set_recv_gro`. `None` for a datagram that arrived on its own.
+    pub segment_size: Option<usize>,
+    // the local address to reply from, unset for a multicast or broadcast destination
+    // unless the platform reported the address it would have picked
+    pub(crate) local_ip: Option<IpAddr>,
+}
+
+impl RecvMeta {
+    pub(crate) fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            dst_ip: None,
+            ifindex: None,
+            ecn: None,
+            timestamp: None,
+            segment_size: None,
+            local_ip: None,
+        }
+    }
+
+    /// A [`SendMeta`] for replying to this datagram from the local address and
+    /// interface it arrived on. The reply isn't ECN marked.
+    ///
+    /// A multicast or broadcast address can't be sent from, the reply to a datagram
+    /// sent to one goes out from the address of the interface it arrived on where the
+    /// platform reports it, and from an address the kernel picks otherwise.
+    pub fn reply(&self) -> SendMeta {
+        SendMeta {
+            addr: self.addr,
+            src_ip: self.local_ip,
+            ifindex: self.ifindex,
+            ecn: None,
+        }
+    }
+}
+
+/// Where and how to send a datagram.
+///
+/// Taken by the `Sink` of a [`WithMeta`] in place of a `SocketAddr`. Setting `src_ip`
+/// or `ifindex` sends the datagram with an `IP_PKTINFO` / `IPV6_PKTINFO` control
+/// message, which is only available on Linux and Apple platforms. Setting `ecn`
+/// sends it with an `IP_TOS` / `IPV6_TCLASS` control message, which is only
+/// available on Linux. Options a platform doesn't support are ignored and the datagram
+/// is sent without them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SendMeta {
+    /// The address to send the datagram to.
+    pub addr: SocketAddr,
+    /// The local address to send the datagram from, the socket's own address when
+    /// `None`.
+    pub src_ip: Option<IpAddr>,
+    /// The index of the interface to send the datagram on, chosen by the routing
+    /// table when `None`.
+    pub ifindex: Option<u32>,
+    /// The ECN codepoint to mark the datagram with, not ECN-capable when `None`.
+    /// Only honoured on Linux, elsewhere the datagram is sent unmarked.
+    pub ecn: Option<EcnCodepoint>,
+}
+
+impl SendMeta {
+    /// Send to `addr` from the socket's own address.
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            src_ip: None,
+            ifindex: None,
+            ecn: None,
+        }
+    }
+
+    /// Send from the local address `src_ip`.
+    pub fn with_src_ip(mut self, src_ip: IpAddr) -> Self {
+        self.src_ip = Some(src_ip);
+        self
+    }
+
+    /// Send on the interface with index `ifindex`.
+    pub fn with_ifindex(mut self, ifindex: u32) -> Self {
+        self.ifindex = Some(ifindex);
+        self
+    }
+
+    /// Mark the datagram with the ECN codepoint `ecn`.
+    pub fn with_ecn(mut self, ecn: EcnCodepoint) -> Self {
+        self.ecn = Some(ecn);
+        self
+    }
+
+    /// Whether the datagram has to be sent with control messages.
+    pub(crate) fn needs_cmsg(&self) -> bool {
+        self.src_ip.is_some() || self.ifindex.is_some() || self.ecn.is_some()
+    }
+}
+
+/// An Explicit Congestion Notification codepoint, the low two bits of the IPv4 TOS
+/// byte or IPv6 traffic class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EcnCodepoint {
+    /// ECN-capable transport, `ECT(0)`.
+    Ect0,
+    /// ECN-capable transport, `ECT(1)`.
+    Ect1,
+    /// Congestion experienced, `CE`.
+    Ce,
+}
+
+impl EcnCodepoint {
+    /// The codepoint in the low two bits of `bits`, `None` for `Not-ECT`.
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        match bits & 0b11 {
+            0b10 => Some(Self::Ect0),
+            0b01 => Some(Self::Ect1),
+            0b11 => Some(Self::Ce),
+            _ => None,
+        }
+    }
+
+    /// The codepoint as the low two bits of a TOS byte.
+    pub fn bits(self) -> u8 {
+        match self {
+            Self::Ect0 => 0b10,
+            Self::Ect1 => 0b01,
+            Self::Ce => 0b11,
+        }
+    }
+}
+
+impl From<SocketAddr> for SendMeta {
+    fn from(addr: SocketAddr) -> Self {
+        Self::new(addr)
+    }
+}
+
+/// A decoded frame together with the metadata of the datagram it came from.
+pub(crate) type MetaItem<C> =
+    Result<(<C as DatagramDecoder>::Item, RecvMeta), <C as DatagramDecoder>::Error>;
+
+pin_project! {
+    /// Wraps a [`UdpFramed`], [`UdpFramedRecv`], [`UdpFramedSend`] or [`UdpDatagrams`]
+    /// so its `Stream` yields a [`RecvMeta`] with each frame instead of just the source
+    /// address, and its `Sink` takes a [`SendMeta`] instead of just the destination
+    /// address.
+    ///
+    /// Created with `with_meta`.
+    ///
+    /// [`UdpFramed`]: crate::UdpFramed
+    /// [`UdpFramedRecv`]: crate::UdpFramedRecv
+    /// [`UdpFramedSend`]: crate::UdpFramedSend
+    /// [`UdpDatagrams`]: crate::UdpDatagrams
+    #[derive(Debug)]
+    pub struct WithMeta<F> {
+        #[pin]
+        inner: F,
+    }
+}
+
+impl<F> WithMeta<F> {
+    pub(crate) fn new(inner: F) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a reference to the wrapped framed type.
+    pub fn get_ref(&self) -> &F {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped framed type.
+    pub fn get_mut(&mut self) -> &mut F {
+        &mut self.inner
+    }
+
+    /// Consumes the `WithMeta`, returning the wrapped framed type.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<T> Stream for WithMeta<UdpDatagrams<T>>
+where
+    T: Borrow<UdpSocket>,
+{
+    type Item = io::Result<(Bytes, RecvMeta)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project().inner.get_mut().poll_next_meta(cx)
+    }
+}
+
+impl<T, C> Stream for WithMeta<UdpFramed<T, C>>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder,
+{
+    type Item = MetaItem<C>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project().inner.poll_next_meta(cx)
+    }
+}
+
+impl<T, C> Stream for WithMeta<UdpFramedRecv<T, C>>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder,
+{
+    type Item = MetaItem<C>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project().inner.poll_next_meta(cx)
+    }
+}
+
+impl<T, I, C> Sink<(I, SendMeta)> for WithMeta<UdpFramed<T, C>>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramEncoder<I>,
+    C::Error: From<io::Error>,
+{
+    type Error = C::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<(I, SocketAddr)>::poll_ready(self.project().inner, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: (I, SendMeta)) -> Result<(), Self::Error> {
+        let (frame, meta) = item;
+        self.project().inner.start_send_meta(frame, meta)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<(I, SocketAddr)>::poll_flush(self.project().inner, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<(I, SocketAddr)>::poll_close(self.project().inner, cx)
+    }
+}
+
+impl<T, I, C> Sink<(I, SendMeta)> for WithMeta<UdpFramedSend<T, C>>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramEncoder<I>,
+    C::Error: From<io::Error>,
+{
+    type Error = C::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<(I, SocketAddr)>::poll_ready(self.project().inner, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: (I, SendMeta)) -> Result<(), Self::Error> {
+        let (frame, meta) = item;
+        self.project().inner.start_send_meta(frame, meta)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<(I, SocketAddr)>::poll_flush(self.project().inner, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<(I, SocketAddr)>::poll_close(self.project().inner, cx)
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{
+    codec::{DatagramDecoder, DatagramEncoder},
+    framed_recv::UdpFramedRecv,
+    framed_send::UdpFramedSend,
+};
+
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc,
+    time::{self, Instant, Interval},
+};
+use tokio_stream::Stream;
+
+use futures_core::ready;
+use futures_sink::Sink;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// How long a peer may go without sending anything before it is evicted, by default.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// The shortest idle timeout, a zero period would make the sweep interval panic.
+const MIN_IDLE_TIMEOUT: Duration = Duration::from_millis(1);
+/// How many frames are buffered for a peer that isn't reading them, by default.
+const DEFAULT_PEER_BUFFER: usize = 64;
+
+/// Splits the frames received on a socket into a [`PeerStream`] and [`PeerSink`]
+/// per source address.
+///
+/// `UdpFramedMux` is a `Stream` of new peers, like a listener's `accept`. The first
+/// frame from an address yields a `(PeerStream, PeerSink)` pair for it, later frames
+/// from the same address are handed to its `PeerStream`. Frames are only received
+/// while the mux itself is polled, so keep polling it, typically spawning a task per
+/// peer as they come in.
+///
+/// A peer that sends nothing for the idle timeout is evicted and its `PeerStream`
+/// ends, the next frame from it yields a new pair. A peer whose `PeerStream` has been
+/// dropped is treated as new as well. Frames for a peer that isn't keeping up with its
+/// buffer are dropped.
+///
+/// Every `PeerSink` sends on the same socket with its own clone of the codec.
+///
+/// ```rust
+/// # use std::{io, sync::Arc};
+/// # use futures::SinkExt;
+/// # use tokio::net::UdpSocket;
+/// # use tokio_stream::StreamExt;
+/// use tokio_util::codec::LinesCodec;
+/// use tokio_udp_framed::UdpFramedMux;
+///
+/// # async fn run() -> io::Result<()> {
+/// let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+/// let mut mux = UdpFramedMux::new(socket, LinesCodec::new());
+///
+/// while let Some(peer) = mux.next().await {
+///     let (mut stream, mut sink) = peer.unwrap();
+///     tokio::spawn(async move {
+///         while let Some(line) = stream.next().await {
+///             sink.send(line).await.unwrap();
+///         }
+///     });
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct UdpFramedMux<C>
+where
+    C: DatagramDecoder,
+{
+    recv: UdpFramedRecv<Arc<UdpSocket>, C>,
+    codec: C,
+    peers: HashMap<SocketAddr, Peer<C::Item>>,
+    idle_timeout: Option<Duration>,
+    peer_buffer: usize,
+    /// Created on the first poll, since a timer needs the runtime.
+    sweep: Option<Interval>,
+}
+
+// nothing in the mux is ever pinned
+impl<C> Unpin for UdpFramedMux<C> where C: DatagramDecoder {}
+
+struct Peer<T> {
+    tx: mpsc::Sender<T>,
+    last_seen: Instant,
+}
+
+impl<C> UdpFramedMux<C>
+where
+    C: DatagramDecoder + Clone,
+{
+    /// Create a new `UdpFramedMux` receiving on `socket` with `codec`.
+    pub fn new(socket: Arc<UdpSocket>, codec: C) -> UdpFramedMux<C> {
+        Self::from_recv(UdpFramedRecv::new(socket, codec.clone()), codec)
+    }
+
+    /// Create a new `UdpFramedMux` from a configured `UdpFramedRecv`, `codec` is cloned
+    /// for each `PeerSink`.
+    pub fn from_recv(recv: UdpFramedRecv<Arc<UdpSocket>, C>, codec: C) -> UdpFramedMux<C> {
+        Self {
+            recv,
+            codec,
+            peers: HashMap::new(),
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            peer_buffer: DEFAULT_PEER_BUFFER,
+            sweep: None,
+        }
+    }
+}
+
+impl<C> UdpFramedMux<C>
+where
+    C: DatagramDecoder,
+{
+    /// Returns a reference to the underlying socket.
+    pub fn get_ref(&self) -> &UdpSocket {
+        self.recv.get_ref()
+    }
+
+    /// Returns the `UdpFramedRecv` frames are received with.
+    pub fn recv(&self) -> &UdpFramedRecv<Arc<UdpSocket>, C> {
+        &self.recv
+    }
+
+    /// Returns a mutable reference to the `UdpFramedRecv` frames are received with.
+    pub fn recv_mut(&mut self) -> &mut UdpFramedRecv<Arc<UdpSocket>, C> {
+        &mut self.recv
+    }
+
+    /// Returns the number of peers currently tracked.
+    pub fn peers(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Returns how long a peer may go without sending anything before it is evicted.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Evict peers that haven't sent anything for `timeout`, or never if `None`. The
+    /// default is 60 seconds.
+    ///
+    /// Peers are checked every `timeout`, so one may linger for up to twice as long.
+    /// A zero timeout is raised to 1 millisecond, the resolution of the timer.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout.map(|timeout| timeout.max(MIN_IDLE_TIMEOUT));
+        self.sweep = None;
+    }
+
+    /// Returns how many frames are buffered for a peer that isn't reading them.
+    pub fn peer_buffer(&self) -> usize {
+        self.peer_buffer
+    }
+
+    /// Buffer up to `frames` frames for each peer, once a peer's buffer is full further
+    /// frames for it are dropped. The default is 64, and it applies to peers seen after
+    /// this is set.
+    pub fn set_peer_buffer(&mut self, frames: usize) {
+        self.peer_buffer = frames.max(1);
+    }
+
+    /// Consumes the `UdpFramedMux`, returning the `UdpFramedRecv` frames were received
+    /// with. Every `PeerStream` ends.
+    pub fn into_inner(self) -> UdpFramedRecv<Arc<UdpSocket>, C> {
+        self.recv
+    }
+
+    fn poll_sweep(&mut self, cx: &mut Context<'_>) {
+        let timeout = match self.idle_timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        let sweep = self
+            .sweep
+            .get_or_insert_with(|| time::interval_at(Instant::now() + timeout, timeout));
+        while sweep.poll_tick(cx).is_ready() {
+            let now = Instant::now();
+            self.peers.retain(|_, peer| {
+                !peer.tx.is_closed() && now.duration_since(peer.last_seen) < timeout
+            });
+        }
+    }
+}
+
+impl<C> Stream for UdpFramedMux<C>
+where
+    C: DatagramDecoder + Clone,
+{
+    type Item = Result<(PeerStream<C::Item>, PeerSink<C>), C::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.poll_sweep(cx);
+
+        loop {
+            let (frame, addr) = match ready!(Pin::new(&mut this.recv).poll_next(cx)) {
+                Some(Ok(item)) => item,
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            };
+
+            if let Some(peer) = this.peers.get_mut(&addr) {
+                match peer.tx.try_send(frame) {
+                    Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => {
+                        peer.last_seen = Instant::now();
+                        continue;
+                    }
+                    // the `PeerStream` is gone, start over as if the peer were new
+                    Err(mpsc::error::TrySendError::Closed(returned)) => {
+                        this.peers.remove(&addr);
+                        return Poll::Ready(Some(Ok(this.accept(returned, addr))));
+                    }
+                }
+            }
+            return Poll::Ready(Some(Ok(this.accept(frame, addr))));
+        }
+    }
+}
+
+impl<C> UdpFramedMux<C>
+where
+    C: DatagramDecoder + Clone,
+{
+    fn accept(&mut self, frame: C::Item, addr: SocketAddr) -> (PeerStream<C::Item>, PeerSink<C>) {
+        let (tx, rx) = mpsc::channel(self.peer_buffer);
+        tx.try_send(frame)
+            .unwrap_or_else(|_| unreachable!("a new channel has room"));
+        self.peers.insert(
+            addr,
+            Peer {
+                tx,
+                last_seen: Instant::now(),
+            },
+        );
+
+        let socket = self.recv.socket().clone();
+        let stream = PeerStream { rx, addr };
+        let sink = PeerSink {
+            send: UdpFramedSend::new(socket, self.codec.clone()),
+            addr,
+        };
+        (stream, sink)
+    }
+}
+
+impl<C> fmt::Debug for UdpFramedMux<C>
+where
+    C: DatagramDecoder + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpFramedMux")
+            .field("recv", &self.recv)
+            .field("peers", &self.peers.len())
+            .field("idle_timeout", &self.idle_timeout)
+            .field("peer_buffer", &self.peer_buffer)
+            .finish()
+    }
+}
+
+/// A [`Stream`] of the frames one peer sent to a [`UdpFramedMux`].
+///
+/// It ends when the peer is evicted or the mux is dropped.
+///
+/// [`Stream`]: tokio::stream::Stream
+#[derive(Debug)]
+pub struct PeerStream<T> {
+    rx: mpsc::Receiver<T>,
+    addr: SocketAddr,
+}
+
+impl<T> PeerStream<T> {
+    /// Returns the address of the peer.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl<T> Stream for PeerStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// A [`Sink`] of frames to one peer of a [`UdpFramedMux`], sent on the mux's socket.
+///
+/// [`Sink`]: futures_sink::Sink
+#[derive(Debug)]
+pub struct PeerSink<C> {
+    send: UdpFramedSend<Arc<UdpSocket>, C>,
+    addr: SocketAddr,
+}
+
+impl<C> PeerSink<C> {
+    /// Returns the address of the peer.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the `UdpFramedSend` frames are sent with.
+    pub fn get_ref(&self) -> &UdpFramedSend<Arc<UdpSocket>, C> {
+        &self.send
+    }
+
+    /// Returns a mutable reference to the `UdpFramedSend` frames are sent with.
+    pub fn get_mut(&mut self) -> &mut UdpFramedSend<Arc<UdpSocket>, C> {
+        &mut self.send
+    }
+}
+
+impl<C, I> Sink<I> for PeerSink<C>
+where
+    C: DatagramEncoder<I>,
+{
+    type Error = C::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<(I, SocketAddr)>::poll_ready(Pin::new(&mut self.send), cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let addr = self.addr;
+        Pin::new(&mut self.send).start_send((item, addr))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<(I, SocketAddr)>::poll_flush(Pin::new(&mut self.send), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<(I, SocketAddr)>::poll_close(Pin::new(&mut self.send), cx)
+    }
+}
@@ -0,0 +1,607 @@
+use crate::{
+    codec::{DatagramDecoder, DatagramEncoder},
+    frame::UdpFramed,
+    meta::RecvMeta,
+};
+
+use tokio::{
+    net::UdpSocket,
+    time::{self, Instant, Sleep},
+};
+use tokio_stream::Stream;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_sink::Sink;
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// How many frames may be waiting for an acknowledgement, by default.
+const DEFAULT_WINDOW: usize = 32;
+/// The most frames that may be waiting for an acknowledgement, what a selective
+/// acknowledgement can cover.
+const MAX_WINDOW: usize = 64;
+const DEFAULT_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(200);
+const DEFAULT_MAX_RETRANSMITS: u32 = 10;
+
+const KIND_DATA: u8 = 0;
+const KIND_ACK: u8 = 1;
+
+/// Delivers frames to one peer reliably and in order, over a [`UdpFramed`].
+///
+/// Every frame sent through the `Sink` is sent as its own datagram with a sequence
+/// number, and sent again until the peer acknowledges it. The peer acknowledges what
+/// it received in order with a cumulative sequence number, and up to 64 frames past
+/// it with a bitmask, so only the frames that were actually lost are retransmitted.
+/// Received frames are put back in order and duplicates dropped before they are
+/// yielded by the `Stream`. Both ends of the conversation need to use `Reliable`.
+///
+/// Datagrams from addresses other than the peer are dropped. The wire format puts a
+/// 9 byte header in front of each encoded frame, acknowledgements are 17 bytes.
+///
+/// Retransmissions and acknowledgements are only sent while the `Reliable` is
+/// polled, through either the `Stream` or the `Sink`. Flushing the `Sink` completes
+/// once the peer has acknowledged every frame sent, and `poll_ready` waits while the
+/// window of unacknowledged frames is full. A frame that isn't acknowledged after the
+/// maximum number of retransmissions gives up on the peer: whichever of the `Sink`
+/// and the `Stream` is polled first returns an `io::Error` of kind `TimedOut`, after
+/// that the `Stream` ends and the `Sink` keeps failing.
+///
+/// There is no flow control on the receiving side. Frames that arrived in order are
+/// buffered until the `Stream` takes them, including frames that arrive while only
+/// the `Sink` is polled, so keep reading if the peer may send a lot.
+///
+/// Sequence numbers start at 0 when a `Reliable` is created and there's no handshake,
+/// so both ends should be created fresh for a conversation.
+///
+/// ```rust
+/// # use std::io;
+/// # use futures::SinkExt;
+/// # use tokio::net::UdpSocket;
+/// # use tokio_stream::StreamExt;
+/// use tokio_util::codec::LinesCodec;
+/// use tokio_udp_framed::Reliable;
+///
+/// # async fn run() -> io::Result<()> {
+/// let socket = UdpSocket::bind("127.0.0.1:0").await?;
+/// let peer = "127.0.0.1:9000".parse().unwrap();
+/// let mut reliable = Reliable::new(socket, LinesCodec::new(), peer);
+///
+/// reliable.send("ping").await.unwrap();
+/// let pong = reliable.next().await.unwrap().unwrap();
+/// # Ok(())
+/// # }
+/// ```
+pub struct Reliable<T, C>
+where
+    C: DatagramDecoder,
+{
+    framed: UdpFramed<T, ReliableCodec<C>>,
+    peer: SocketAddr,
+    window: usize,
+    retransmit_timeout: Duration,
+    max_retransmits: u32,
+    send: SendState,
+    recv: RecvState<C::Item>,
+    recv_error: Option<C::Error>,
+    recv_done: bool,
+    retransmit: Option<Pin<Box<Sleep>>>,
+    /// A frame ran out of retransmissions, nothing more is sent or received.
+    gave_up: bool,
+}
+
+// nothing in a `Reliable` is ever pinned
+impl<T, C> Unpin for Reliable<T, C>
+where
+    T: Unpin,
+    C: DatagramDecoder,
+{
+}
+
+#[derive(Debug, Default)]
+struct SendState {
+    next_seq: u64,
+    unacked: BTreeMap<u64, Unacked>,
+    /// Sequence numbers of the frames to send, in order.
+    queued: VecDeque<u64>,
+    flushed: bool,
+}
+
+#[derive(Debug)]
+struct Unacked {
+    payload: Bytes,
+    sent_at: Option<Instant>,
+    retransmits: u32,
+}
+
+struct RecvState<T> {
+    /// Every frame before this one has been delivered.
+    next_seq: u64,
+    out_of_order: BTreeMap<u64, Vec<T>>,
+    ready: VecDeque<T>,
+    ack_due: bool,
+}
+
+impl<T> RecvState<T> {
+    fn receive(&mut self, seq: u64, items: Vec<T>) {
+        // anything already delivered or buffered is acknowledged again, in case the
+        // acknowledgement was lost
+        self.ack_due = true;
+        if seq < self.next_seq || seq > self.next_seq + MAX_WINDOW as u64 {
+            return;
+        }
+        self.out_of_order.entry(seq).or_insert(items);
+        while let Some(items) = self.out_of_order.remove(&self.next_seq) {
+            self.ready.extend(items);
+            self.next_seq += 1;
+        }
+    }
+
+    fn selective_ack(&self) -> u64 {
+        self.out_of_order
+            .keys()
+            .map(|seq| seq - self.next_seq - 1)
+            .filter(|bit| *bit < 64)
+            .fold(0, |mask, bit| mask | (1 << bit))
+    }
+}
+
+impl<T, C> Reliable<T, C>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder,
+{
+    /// Create a new `Reliable` exchanging frames encoded with `codec` with `peer` on
+    /// `socket`.
+    pub fn new(socket: T, codec: C, peer: SocketAddr) -> Reliable<T, C> {
+        Self::from_framed(UdpFramed::new(socket, codec), peer)
+    }
+
+    /// Create a new `Reliable` exchanging frames with `peer` on a configured
+    /// `UdpFramed`.
+    pub fn from_framed(framed: UdpFramed<T, C>, peer: SocketAddr) -> Reliable<T, C> {
+        Self {
+            framed: framed.map_codec(|codec| ReliableCodec {
+                codec,
+                decoded: false,
+            }),
+            peer,
+            window: DEFAULT_WINDOW,
+            retransmit_timeout: DEFAULT_RETRANSMIT_TIMEOUT,
+            max_retransmits: DEFAULT_MAX_RETRANSMITS,
+            send: SendState {
+                flushed: true,
+                ..SendState::default()
+            },
+            recv: RecvState {
+                next_seq: 0,
+                out_of_order: BTreeMap::new(),
+                ready: VecDeque::new(),
+                ack_due: false,
+            },
+            recv_error: None,
+            recv_done: false,
+            retransmit: None,
+            gave_up: false,
+        }
+    }
+
+    /// Returns a reference to the underlying socket.
+    pub fn get_ref(&self) -> &UdpSocket {
+        self.framed.get_ref()
+    }
+
+    /// Returns a reference to the codec.
+    pub fn codec(&self) -> &C {
+        &self.framed.codec().codec
+    }
+
+    /// Returns the address of the peer.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Returns the number of frames sent that the peer hasn't acknowledged yet.
+    pub fn unacked(&self) -> usize {
+        self.send.unacked.len()
+    }
+
+    /// Returns how many frames may be waiting for an acknowledgement.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Let up to `frames` frames wait for an acknowledgement before `poll_ready` waits
+    /// for the peer. The default is 32, and it can't be more than 64.
+    pub fn set_window(&mut self, frames: usize) {
+        self.window = frames.clamp(1, MAX_WINDOW);
+    }
+
+    /// Returns how long to wait for an acknowledgement before sending a frame again.
+    pub fn retransmit_timeout(&self) -> Duration {
+        self.retransmit_timeout
+    }
+
+    /// Send a frame again if it isn't acknowledged within `timeout`. The default is
+    /// 200 milliseconds.
+    pub fn set_retransmit_timeout(&mut self, timeout: Duration) {
+        self.retransmit_timeout = timeout;
+    }
+
+    /// Returns how many times a frame is sent again before giving up on the peer.
+    pub fn max_retransmits(&self) -> u32 {
+        self.max_retransmits
+    }
+
+    /// Send a frame again up to `retransmits` times. The default is 10.
+    pub fn set_max_retransmits(&mut self, retransmits: u32) {
+        self.max_retransmits = retransmits;
+    }
+
+    /// Consumes the `Reliable`, returning the `UdpFramed` it used. Frames not yet
+    /// acknowledged or delivered are dropped.
+    pub fn into_inner(self) -> UdpFramed<T, C> {
+        self.framed.map_codec(|codec| codec.codec)
+    }
+}
+
+impl<T, C> Reliable<T, C>
+where
+    T: Borrow<UdpSocket> + Unpin,
+    C: DatagramDecoder,
+{
+    /// Receive what has arrived, send acknowledgements and due retransmissions, and
+    /// register for wake ups when there is more to do.
+    fn poll_drive(&mut self, cx: &mut Context<'_>) -> Result<(), io::Error> {
+        if self.gave_up {
+            return Ok(());
+        }
+        while !self.recv_done {
+            match Pin::new(&mut self.framed).poll_next(cx) {
+                Poll::Ready(Some(Ok((packet, addr)))) if addr == self.peer => match packet {
+                    Packet::Data { seq, items } => self.recv.receive(seq, items),
+                    Packet::Ack { next_seq, mask } => self.acknowledged(next_seq, mask),
+                },
+                Poll::Ready(Some(Ok(_))) => {}
+                // the `Stream` returns the first error, the `Sink` carries on
+                Poll::Ready(Some(Err(err))) => {
+                    self.recv_error.get_or_insert(err);
+                    break;
+                }
+                Poll::Ready(None) => self.recv_done = true,
+                Poll::Pending => break,
+            }
+        }
+
+        loop {
+            let now = Instant::now();
+            for (seq, unacked) in self.send.unacked.iter_mut() {
+                match unacked.sent_at {
+                    Some(sent_at) if sent_at + self.retransmit_timeout <= now => {}
+                    // not due yet, or already queued
+                    _ => continue,
+                }
+                if unacked.retransmits == self.max_retransmits {
+                    let err = io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!(
+                            "{} did not acknowledge frame {} after {} retransmissions",
+                            self.peer, seq, self.max_retransmits
+                        ),
+                    );
+                    self.give_up();
+                    return Err(err);
+                }
+                unacked.retransmits += 1;
+                unacked.sent_at = None;
+                self.send.queued.push_back(*seq);
+            }
+
+            self.poll_send(cx)?;
+
+            // frames still queued get a deadline once they are sent
+            let deadline = match self.send.unacked.values().filter_map(|u| u.sent_at).min() {
+                Some(sent_at) => sent_at + self.retransmit_timeout,
+                None => {
+                    self.retransmit = None;
+                    return Ok(());
+                }
+            };
+            let retransmit = self
+                .retransmit
+                .get_or_insert_with(|| Box::pin(time::sleep_until(deadline)));
+            retransmit.as_mut().reset(deadline);
+            if retransmit.as_mut().poll(cx).is_pending() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Result<(), io::Error> {
+        let mut framed = Pin::new(&mut self.framed);
+        loop {
+            let packet = if self.recv.ack_due {
+                Outgoing::Ack {
+                    next_seq: self.recv.next_seq,
+                    mask: self.recv.selective_ack(),
+                }
+            } else {
+                match self.send.queued.front() {
+                    Some(seq) => Outgoing::Data {
+                        seq: *seq,
+                        payload: self.send.unacked[seq].payload.clone(),
+                    },
+                    None => break,
+                }
+            };
+            match Sink::<(Outgoing, SocketAddr)>::poll_ready(framed.as_mut(), cx)? {
+                Poll::Ready(()) => {}
+                Poll::Pending => break,
+            }
+            match packet {
+                Outgoing::Ack { .. } => self.recv.ack_due = false,
+                Outgoing::Data { seq, .. } => {
+                    self.send.queued.pop_front();
+                    if let Some(unacked) = self.send.unacked.get_mut(&seq) {
+                        unacked.sent_at = Some(Instant::now());
+                    }
+                }
+            }
+            framed.as_mut().start_send((packet, self.peer))?;
+            self.send.flushed = false;
+        }
+
+        if !self.send.flushed {
+            if let Poll::Ready(flushed) =
+                Sink::<(Outgoing, SocketAddr)>::poll_flush(framed.as_mut(), cx)
+            {
+                flushed?;
+                self.send.flushed = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn give_up(&mut self) {
+        self.gave_up = true;
+        self.send.unacked.clear();
+        self.send.queued.clear();
+        self.retransmit = None;
+    }
+
+    fn gave_up_error(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "gave up on {} after it stopped acknowledging frames",
+                self.peer
+            ),
+        )
+    }
+
+    fn acknowledged(&mut self, next_seq: u64, mask: u64) {
+        self.send.unacked = self.send.unacked.split_off(&next_seq);
+        for bit in 0..64 {
+            if mask & (1 << bit) != 0 {
+                self.send.unacked.remove(&(next_seq + 1 + bit));
+            }
+        }
+        let unacked = &self.send.unacked;
+        self.send.queued.retain(|seq| unacked.contains_key(seq));
+    }
+}
+
+impl<T, C> Stream for Reliable<T, C>
+where
+    T: Borrow<UdpSocket> + Unpin,
+    C: DatagramDecoder,
+{
+    type Item = Result<C::Item, C::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Err(err) = this.poll_drive(cx) {
+            return Poll::Ready(Some(Err(err.into())));
+        }
+        if let Some(item) = this.recv.ready.pop_front() {
+            return Poll::Ready(Some(Ok(item)));
+        }
+        if let Some(err) = this.recv_error.take() {
+            return Poll::Ready(Some(Err(err)));
+        }
+        if this.recv_done || this.gave_up {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+impl<T, C, I> Sink<I> for Reliable<T, C>
+where
+    T: Borrow<UdpSocket> + Unpin,
+    C: DatagramDecoder + DatagramEncoder<I>,
+{
+    type Error = <C as DatagramEncoder<I>>::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        this.poll_drive(cx)?;
+        if this.gave_up {
+            return Poll::Ready(Err(this.gave_up_error().into()));
+        }
+        if this.send.unacked.len() < this.window {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        if this.gave_up {
+            return Err(this.gave_up_error().into());
+        }
+        let mut payload = BytesMut::new();
+        this.framed
+            .codec_mut()
+            .codec
+            .encode_datagram(item, &mut payload)?;
+
+        let seq = this.send.next_seq;
+        this.send.next_seq += 1;
+        this.send.unacked.insert(
+            seq,
+            Unacked {
+                payload: payload.freeze(),
+                sent_at: None,
+                retransmits: 0,
+            },
+        );
+        this.send.queued.push_back(seq);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        this.poll_drive(cx)?;
+        if this.gave_up {
+            return Poll::Ready(Err(this.gave_up_error().into()));
+        }
+        if this.send.unacked.is_empty() && this.send.flushed {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl<T, C> fmt::Debug for Reliable<T, C>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reliable")
+            .field("framed", &self.framed)
+            .field("peer", &self.peer)
+            .field("window", &self.window)
+            .field("retransmit_timeout", &self.retransmit_timeout)
+            .field("max_retransmits", &self.max_retransmits)
+            .field("unacked", &self.send.unacked.len())
+            .field("recv_next_seq", &self.recv.next_seq)
+            .finish()
+    }
+}
+
+/// Puts the sequence number or acknowledgement header in front of the frames of the
+/// wrapped codec.
+#[derive(Debug)]
+struct ReliableCodec<C> {
+    codec: C,
+    /// A packet was decoded from the current datagram, what is left of it is trailing
+    /// data.
+    decoded: bool,
+}
+
+enum Packet<T> {
+    Data { seq: u64, items: Vec<T> },
+    Ack { next_seq: u64, mask: u64 },
+}
+
+enum Outgoing {
+    Data { seq: u64, payload: Bytes },
+    Ack { next_seq: u64, mask: u64 },
+}
+
+impl<C> DatagramDecoder for ReliableCodec<C>
+where
+    C: DatagramDecoder,
+{
+    type Item = Packet<C::Item>;
+    type Error = C::Error;
+
+    fn decode_datagram(
+        &mut self,
+        datagram: &mut BytesMut,
+        meta: &RecvMeta,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        if self.decoded {
+            self.decoded = false;
+            return Ok(None);
+        }
+        let len = match datagram.first() {
+            Some(&KIND_DATA) => 9,
+            Some(&KIND_ACK) => 17,
+            // not sent by a `Reliable`
+            _ => {
+                datagram.clear();
+                return Ok(None);
+            }
+        };
+        if datagram.len() < len {
+            datagram.clear();
+            return Ok(None);
+        }
+
+        let kind = datagram.get_u8();
+        let seq = datagram.get_u64();
+        if kind == KIND_ACK {
+            let mask = datagram.get_u64();
+            datagram.clear();
+            self.decoded = true;
+            return Ok(Some(Packet::Ack {
+                next_seq: seq,
+                mask,
+            }));
+        }
+
+        self.decoded = true;
+        let mut items = Vec::new();
+        loop {
+            match self.codec.decode_datagram(datagram, meta) {
+                Ok(Some(item)) => items.push(item),
+                Ok(None) => return Ok(Some(Packet::Data { seq, items })),
+                // drop the whole packet without acknowledging it, so the peer sends it
+                // again, and don't let what's left be taken for another packet
+                Err(err) => {
+                    datagram.clear();
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+impl<C> DatagramEncoder<Outgoing> for ReliableCodec<C> {
+    type Error = io::Error;
+
+    fn encode_datagram(&mut self, packet: Outgoing, dst: &mut BytesMut) -> Result<(), io::Error> {
+        match packet {
+            Outgoing::Data { seq, payload } => {
+                dst.reserve(9 + payload.len());
+                dst.put_u8(KIND_DATA);
+                dst.put_u64(seq);
+                dst.put_slice(&payload);
+            }
+            Outgoing::Ack { next_seq, mask } => {
+                dst.reserve(17);
+                dst.put_u8(KIND_ACK);
+                dst.put_u64(next_seq);
+                dst.put_u64(mask);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{
+    codec::{DatagramDecoder, DatagramEncoder, Resilient},
+    framed_recv::UdpFramedRecv,
+    framed_send::UdpFramedSend,
+    meta::{RecvMeta, SendMeta, WithMeta},
+};
+
+use tokio::{net::UdpSocket, task::JoinSet};
+use tokio_stream::StreamExt;
+
+use futures_sink::Sink;
+use std::{
+    borrow::Borrow,
+    fmt,
+    future::{poll_fn, Future},
+    net::SocketAddr,
+    pin::Pin,
+};
+
+const DEFAULT_CONCURRENCY_LIMIT: usize = 256;
+
+/// Handles one request received by a [`UdpServer`].
+///
+/// Like a `tower::Service<(Request, RecvMeta)>`, `call` returns a future that is run
+/// on its own task. It resolves to the response to send back, or `None` to send
+/// nothing. Any `FnMut(Request, RecvMeta) -> impl Future<Output = Option<Response>>`
+/// is a `Handler`.
+pub trait Handler<Request> {
+    /// The type of responses.
+    type Response;
+    /// The future handling a request.
+    type Future: Future<Output = Option<Self::Response>>;
+
+    /// Handle `request`, received with `meta`.
+    fn call(&mut self, request: Request, meta: RecvMeta) -> Self::Future;
+}
+
+impl<F, Fut, Request, Response> Handler<Request> for F
+where
+    F: FnMut(Request, RecvMeta) -> Fut,
+    Fut: Future<Output = Option<Response>>,
+{
+    type Response = Response;
+    type Future = Fut;
+
+    fn call(&mut self, request: Request, meta: RecvMeta) -> Fut {
+        self(request, meta)
+    }
+}
+
+type SkipErrors<C> = Resilient<C, fn(<C as DatagramDecoder>::Error, SocketAddr)>;
+type OnSendError<C> = Box<dyn FnMut(<C as DatagramDecoder>::Error, SocketAddr) + Send>;
+
+/// Receives requests on a socket, runs a [`Handler`] for each one and sends the
+/// responses back.
+///
+/// Handlers run concurrently on their own tasks, up to the concurrency limit, past
+/// which no more datagrams are received until one finishes. A response is sent to
+/// the address the request came from, through the local address and interface it
+/// arrived on if [`set_recv_pktinfo`] was enabled on the `UdpFramedRecv`.
+///
+/// Datagrams the codec fails to decode, truncated datagrams and ICMP-induced receive
+/// errors are skipped, any other receive error stops the server. A response that
+/// can't be sent, because it is too large or the kernel rejects the address it is
+/// sent to, is dropped and handed to the [`on_send_error`] callback if there is one,
+/// the server keeps serving.
+///
+/// [`set_recv_pktinfo`]: crate::UdpFramedRecv::set_recv_pktinfo
+/// [`on_send_error`]: UdpServer::on_send_error
+///
+/// ```rust
+/// # use std::io;
+/// # use tokio::net::UdpSocket;
+/// use tokio_util::codec::LinesCodec;
+/// use tokio_udp_framed::UdpServer;
+///
+/// # async fn run() -> io::Result<()> {
+/// let socket = UdpSocket::bind("127.0.0.1:0").await?;
+/// let server = UdpServer::new(&socket, LinesCodec::new(), |line: String, _meta| async move {
+///     Some(line.to_uppercase())
+/// });
+/// server
+///     .serve_with_shutdown(async { tokio::signal::ctrl_c().await.unwrap() })
+///     .await
+///     .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct UdpServer<T, C, H>
+where
+    C: DatagramDecoder,
+{
+    recv: WithMeta<UdpFramedRecv<T, SkipErrors<C>>>,
+    send: WithMeta<UdpFramedSend<T, C>>,
+    handler: H,
+    concurrency_limit: usize,
+    on_send_error: Option<OnSendError<C>>,
+}
+
+impl<T, C, H> UdpServer<T, C, H>
+where
+    T: Borrow<UdpSocket> + Clone + Unpin,
+    C: DatagramDecoder + Clone,
+{
+    /// Create a new `UdpServer` on `socket`, receiving and sending with clones of
+    /// `codec`.
+    pub fn new(socket: T, codec: C, handler: H) -> UdpServer<T, C, H> {
+        let recv = UdpFramedRecv::new(socket.clone(), codec.clone());
+        Self::from_parts(recv, UdpFramedSend::new(socket, codec), handler)
+    }
+}
+
+impl<T, C, H> UdpServer<T, C, H>
+where
+    T: Borrow<UdpSocket> + Unpin,
+    C: DatagramDecoder,
+{
+    /// Create a new `UdpServer` from a configured `UdpFramedRecv` and `UdpFramedSend`,
+    /// which would normally share a socket.
+    pub fn from_parts(
+        mut recv: UdpFramedRecv<T, C>,
+        send: UdpFramedSend<T, C>,
+        handler: H,
+    ) -> UdpServer<T, C, H> {
+        recv.set_recv_ignore_icmp_errors(true);
+        Self {
+            recv: recv.resilient(skip as fn(_, _)).with_meta(),
+            send: send.with_meta(),
+            handler,
+            concurrency_limit: DEFAULT_CONCURRENCY_LIMIT,
+            on_send_error: None,
+        }
+    }
+
+    /// Returns the most handlers that run at once.
+    pub fn concurrency_limit(&self) -> usize {
+        self.concurrency_limit
+    }
+
+    /// Run at most `limit` handlers at once. The default is 256.
+    pub fn set_concurrency_limit(&mut self, limit: usize) {
+        self.concurrency_limit = limit.max(1);
+    }
+
+    /// Returns a reference to the handler.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Call `f` with the error and the destination of every response that fails to
+    /// send. By default these are dropped silently.
+    pub fn on_send_error<F>(&mut self, f: F)
+    where
+        F: FnMut(<C as DatagramDecoder>::Error, SocketAddr) + Send + 'static,
+    {
+        self.on_send_error = Some(Box::new(f));
+    }
+
+    /// Serve requests until receiving fails.
+    pub async fn serve<Request>(self) -> Result<(), <C as DatagramDecoder>::Error>
+    where
+        C: DatagramDecoder<Item = Request>
+            + DatagramEncoder<H::Response, Error = <C as DatagramDecoder>::Error>,
+        H: Handler<Request>,
+        H::Future: Send + 'static,
+        H::Response: Send + 'static,
+    {
+        self.serve_with_shutdown(std::future::pending()).await
+    }
+
+    /// Serve requests until `shutdown` completes or receiving fails.
+    ///
+    /// Once `shutdown` completes no more requests are received, the handlers still
+    /// running are waited for and their responses sent before this returns.
+    pub async fn serve_with_shutdown<Request, S>(
+        mut self,
+        shutdown: S,
+    ) -> Result<(), <C as DatagramDecoder>::Error>
+    where
+        C: DatagramDecoder<Item = Request>
+            + DatagramEncoder<H::Response, Error = <C as DatagramDecoder>::Error>,
+        H: Handler<Request>,
+        H::Future: Send + 'static,
+        H::Response: Send + 'static,
+        S: Future<Output = ()>,
+    {
+        tokio::pin!(shutdown);
+        let mut in_flight = JoinSet::<(Option<H::Response>, SendMeta)>::new();
+        let mut draining = false;
+
+        loop {
+            tokio::select! {
+                biased;
+
+                _ = &mut shutdown, if !draining => draining = true,
+                Some(done) = in_flight.join_next() => {
+                    // a handler that panicked has nothing to send
+                    if let Ok((Some(response), meta)) = done {
+                        let addr = meta.addr;
+                        if let Err(err) = self.send_response(response, meta).await {
+                            self.send_failed(err, addr);
+                        }
+                    }
+                }
+                request = self.recv.next(),
+                    if !draining && in_flight.len() < self.concurrency_limit =>
+                {
+                    match request {
+                        Some(Ok((request, meta))) => {
+                            let reply = meta.reply();
+                            let handling = self.handler.call(request, meta);
+                            in_flight.spawn(async move { (handling.await, reply) });
+                        }
+                        Some(Err(err)) => return Err(err),
+                        None => draining = true,
+                    }
+                }
+                else => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn send_failed(&mut self, err: <C as DatagramDecoder>::Error, addr: SocketAddr) {
+        if let Some(on_send_error) = self.on_send_error.as_mut() {
+            on_send_error(err, addr);
+        }
+    }
+
+    async fn send_response<I>(
+        &mut self,
+        response: I,
+        meta: SendMeta,
+    ) -> Result<(), <C as DatagramEncoder<I>>::Error>
+    where
+        C: DatagramEncoder<I>,
+    {
+        let mut send = Pin::new(&mut self.send);
+        poll_fn(|cx| Sink::<(I, SendMeta)>::poll_ready(send.as_mut(), cx)).await?;
+        send.as_mut().start_send((response, meta))?;
+        poll_fn(|cx| Sink::<(I, SendMeta)>::poll_flush(send.as_mut(), cx)).await
+    }
+}
+
+impl<T, C, H> fmt::Debug for UdpServer<T, C, H>
+where
+    T: Borrow<UdpSocket>,
+    C: DatagramDecoder + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpServer")
+            .field("recv", self.recv.get_ref())
+            .field("send", self.send.get_ref())
+            .field("concurrency_limit", &self.concurrency_limit)
+            .finish()
+    }
+}
+
+fn skip<E>(_: E, _: SocketAddr) {}
@@ -0,0 +1,137 @@
+//! Helpers for reading and writing ancillary data (control messages) on a `msghdr`.
+use std::{mem, ptr};
+
+/// Space reserved for control messages on a single `msghdr`, enough for every message
+/// that may be sent or received with one datagram at once: `IP_PKTINFO` and
+/// `IPV6_PKTINFO` (12 and 20 bytes, a dual-stack socket may get both), `IP_TOS` and
+/// `IPV6_TCLASS`, a timestamp and a GSO or GRO segment size.
+pub(crate) const CMSG_LEN: usize = space(12)
+    + space(20)
+    + 2 * space(mem::size_of::<libc::c_int>())
+    + space(mem::size_of::<libc::timespec>())
+    + space(mem::size_of::<libc::c_int>());
+
+/// An upper bound for `CMSG_SPACE(len)`, platforms align control messages to at most
+/// a `usize`.
+const fn space(len: usize) -> usize {
+    const fn align(len: usize) -> usize {
+        let align = mem::size_of::<usize>();
+        (len + align - 1) & !(align - 1)
+    }
+    align(mem::size_of::<libc::cmsghdr>()) + align(len)
+}
+
+/// A control message buffer aligned for `cmsghdr`.
+#[derive(Copy, Clone)]
+#[repr(align(8))]
+pub(crate) struct CmsgBuf(pub(crate) [u8; CMSG_LEN]);
+
+impl Default for CmsgBuf {
+    fn default() -> Self {
+        Self([0; CMSG_LEN])
+    }
+}
+
+/// Writes control messages into a `CmsgBuf` and points a `msghdr` at them.
+pub(crate) struct Encoder<'a> {
+    hdr: &'a mut libc::msghdr,
+    cmsg: *mut libc::cmsghdr,
+    len: usize,
+}
+
+impl<'a> Encoder<'a> {
+    /// # Safety
+    ///
+    /// `buf` must outlive every use of `hdr`.
+    pub(crate) unsafe fn new(hdr: &'a mut libc::msghdr, buf: &mut CmsgBuf) -> Self {
+        hdr.msg_control = buf.0.as_mut_ptr() as *mut libc::c_void;
+        hdr.msg_controllen = CMSG_LEN as _;
+        let cmsg = libc::CMSG_FIRSTHDR(hdr);
+        Self { hdr, cmsg, len: 0 }
+    }
+
+    /// Append a control message holding `value`.
+    ///
+    /// # Panics
+    ///
+    /// If the buffer has no space left.
+    pub(crate) fn push<T: Copy>(&mut self, level: libc::c_int, ty: libc::c_int, value: T) {
+        assert!(!self.cmsg.is_null(), "control message buffer is full");
+        let space = unsafe { libc::CMSG_SPACE(mem::size_of::<T>() as _) } as usize;
+        assert!(
+            self.len + space <= CMSG_LEN,
+            "control message buffer is full"
+        );
+
+        // SAFETY: `cmsg` is non-null and has room for a message of this size
+        unsafe {
+            let cmsg = &mut *self.cmsg;
+            cmsg.cmsg_level = level;
+            cmsg.cmsg_type = ty;
+            cmsg.cmsg_len = libc::CMSG_LEN(mem::size_of::<T>() as _) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut T, value);
+        }
+        self.len += space;
+        // SAFETY: `hdr` and `cmsg` point into the same live buffer
+        self.cmsg = unsafe { libc::CMSG_NXTHDR(self.hdr, self.cmsg) };
+    }
+
+    /// Set the final control length on the `msghdr`, clearing it if nothing was pushed.
+    pub(crate) fn finish(self) {
+        self.hdr.msg_controllen = self.len as _;
+        if self.len == 0 {
+            self.hdr.msg_control = ptr::null_mut();
+        }
+    }
+}
+/// Read the value of a control message.
+///
+/// # Safety
+///
+/// The message data must hold a valid `T`.
+// `cmsg_len` is a `usize` on some platforms and a `u32` on others
+#[allow(clippy::unnecessary_cast)]
+pub(crate) unsafe fn decode<T: Copy>(cmsg: &libc::cmsghdr) -> T {
+    debug_assert!(cmsg.cmsg_len as usize >= libc::CMSG_LEN(mem::size_of::<T>() as _) as usize);
+    ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const T)
+}
+
+/// Iterates over the control messages the kernel wrote into a `msghdr`.
+pub(crate) struct Iter<'a> {
+    hdr: &'a libc::msghdr,
+    cmsg: Option<&'a libc::cmsghdr>,
+}
+
+impl<'a> Iter<'a> {
+    /// # Safety
+    ///
+    /// `hdr.msg_control` must point at `hdr.msg_controllen` bytes of control messages
+    /// written by the kernel.
+    pub(crate) unsafe fn new(hdr: &'a libc::msghdr) -> Self {
+        // with `MSG_CTRUNC` set the kernel had more to say than fits, e.g. for options
+        // enabled on a shared socket outside this crate, only the messages that fit
+        // whole are read
+        Self {
+            hdr,
+            cmsg: libc::CMSG_FIRSTHDR(hdr).as_ref(),
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a libc::cmsghdr;
+
+    // `cmsg_len` is a `usize` on some platforms and a `u32` on others
+    #[allow(clippy::unnecessary_cast)]
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.cmsg.take()?;
+        // the last message is cut short when the control data was truncated
+        let end = self.hdr.msg_control as usize + self.hdr.msg_controllen as usize;
+        if current as *const _ as usize + current.cmsg_len as usize > end {
+            return None;
+        }
+        // SAFETY: `current` was returned by a previous `CMSG_*HDR` call on `hdr`
+        self.cmsg = unsafe { libc::CMSG_NXTHDR(self.hdr, current).as_ref() };
+        Some(current)
+    }
+}
@@ -0,0 +1,253 @@
+use std::{collections::VecDeque, io, mem, ops::Range, os::unix::io::AsRawFd, ptr};
+
+use bytes::Bytes;
+use tokio::net::UdpSocket;
+
+use super::{
+    cmsg::{self, CmsgBuf},
+    RecvInfo,
+};
+use crate::meta::{RecvMeta, SendMeta};
+
+/// The kernel won't send more than `UIO_MAXIOV` messages in one `sendmmsg` call.
+const MAX_MMSG: usize = 1024;
+/// The kernel's `UDP_MAX_SEGMENTS`, the most datagrams one GSO send can be split into.
+const GSO_MAX_SEGMENTS: usize = 64;
+/// Keep GSO sends within the largest payload a single IP packet can carry.
+const GSO_MAX_BYTES: usize = 65_000;
+
+/// Receive as many datagrams as will fit in `buf` with a single `recvmmsg` call.
+///
+/// `buf` is divided into slots of `datagram_size` bytes, one per message. The range
+/// of `buf` holding each datagram is pushed onto `out` together with its metadata
+/// and whether it was truncated. When UDP GRO is enabled a message may hold several
+/// coalesced datagrams, these are split at the segment size the kernel reports.
+/// Returns the number of datagrams received.
+pub(crate) fn recv_mmsg(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    datagram_size: usize,
+    out: &mut VecDeque<(Range<usize>, RecvInfo)>,
+) -> io::Result<usize> {
+    let mut iovs = buf
+        .chunks_exact_mut(datagram_size)
+        .map(|slot| libc::iovec {
+            iov_base: slot.as_mut_ptr() as *mut libc::c_void,
+            iov_len: slot.len(),
+        })
+        .collect::<Vec<_>>();
+    // SAFETY: an all-zero `sockaddr_storage` is a valid value
+    let mut addrs = vec![unsafe { mem::zeroed::<libc::sockaddr_storage>() }; iovs.len()];
+    let mut cmsgs = vec![CmsgBuf::default(); iovs.len()];
+    let mut hdrs = iovs
+        .iter_mut()
+        .zip(addrs.iter_mut())
+        .zip(cmsgs.iter_mut())
+        .map(|((iov, addr), cmsg)| {
+            // SAFETY: an all-zero `mmsghdr` is a valid value
+            let mut hdr: libc::mmsghdr = unsafe { mem::zeroed() };
+            hdr.msg_hdr.msg_name = addr as *mut _ as *mut libc::c_void;
+            hdr.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+            hdr.msg_hdr.msg_iov = iov;
+            hdr.msg_hdr.msg_iovlen = 1;
+            hdr.msg_hdr.msg_control = cmsg.0.as_mut_ptr() as *mut libc::c_void;
+            hdr.msg_hdr.msg_controllen = cmsg::CMSG_LEN as _;
+            hdr
+        })
+        .collect::<Vec<_>>();
+
+    // SAFETY: every header points at a live iovec, sockaddr_storage and control buffer
+    // for the duration of the call
+    let n = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            hdrs.as_mut_ptr(),
+            hdrs.len() as _,
+            0,
+            ptr::null_mut(),
+        )
+    };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let before = out.len();
+    for (i, hdr) in hdrs[..n as usize].iter().enumerate() {
+        let mut info = RecvInfo {
+            meta: RecvMeta::new(super::sockaddr_to_addr(&addrs[i], hdr.msg_hdr.msg_namelen)?),
+            truncated: hdr.msg_hdr.msg_flags & libc::MSG_TRUNC != 0,
+        };
+        let start = i * datagram_size;
+        let len = hdr.msg_len as usize;
+
+        // SAFETY: the control buffer was filled in by the kernel
+        for cmsg in unsafe { cmsg::Iter::new(&hdr.msg_hdr) } {
+            if (cmsg.cmsg_level, cmsg.cmsg_type) == (libc::SOL_UDP, libc::UDP_GRO) {
+                // SAFETY: `UDP_GRO` messages hold the segment size as a `c_int`
+                let segment = unsafe { cmsg::decode::<libc::c_int>(cmsg) } as usize;
+                info.meta.segment_size = Some(segment).filter(|&segment| segment > 0);
+            } else {
+                super::decode_recv_cmsg(cmsg, &mut info.meta);
+            }
+        }
+
+        let segment = info.meta.segment_size.unwrap_or(len);
+        if len == 0 || segment == 0 {
+            out.push_back((start..start + len, info));
+            continue;
+        }
+        for offset in (0..len).step_by(segment) {
+            let end = (offset + segment).min(len);
+            out.push_back((start + offset..start + end, info));
+        }
+    }
+
+    Ok(out.len() - before)
+}
+
+/// The number of datagrams from `start` in `queue` that can be sent as one segmented
+/// message: the same `SendMeta` and the same size, except for a shorter last one.
+///
+/// The kernel rejects segments that don't fit in the path MTU with `EINVAL` or
+/// `EMSGSIZE`, a datagram larger than `max_segment` is never segmented.
+pub(crate) fn gso_run(
+    queue: &VecDeque<(Bytes, SendMeta)>,
+    start: usize,
+    max_segment: usize,
+) -> usize {
+    let (first, meta) = &queue[start];
+    let segment = first.len();
+    if segment == 0 || segment > max_segment {
+        return 1;
+    }
+
+    let mut count = 1;
+    let mut total = segment;
+    while let Some((next, next_meta)) = queue.get(start + count) {
+        if next_meta != meta
+            || next.is_empty()
+            || next.len() > segment
+            || total + next.len() > GSO_MAX_BYTES
+            || count == GSO_MAX_SEGMENTS
+        {
+            break;
+        }
+        total += next.len();
+        count += 1;
+        // only the last segment may be shorter
+        if next.len() < segment {
+            break;
+        }
+    }
+    count
+}
+
+/// Enable or disable UDP generic receive offload (`UDP_GRO`) on `socket`.
+pub(crate) fn set_gro(socket: &UdpSocket, enabled: bool) -> io::Result<()> {
+    super::setsockopt(socket, libc::SOL_UDP, libc::UDP_GRO, enabled as libc::c_int)
+}
+
+/// Whether the kernel supports UDP generic segmentation offload (`UDP_SEGMENT`) on `socket`.
+pub(crate) fn gso_supported(socket: &UdpSocket) -> bool {
+    let mut val: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `val` and `len` are valid for the size passed
+    let res = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            libc::UDP_SEGMENT,
+            &mut val as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    res == 0
+}
+
+/// Send the datagrams at the front of `queue` with a single `sendmmsg` call.
+///
+/// With `gso` set, runs of datagrams with the same `SendMeta` and of the same size
+/// (the last one may be shorter) are sent as one message with a `UDP_SEGMENT` control
+/// message, the kernel then splits it back into the individual datagrams. Datagrams
+/// larger than `max_segment` are always sent on their own, see [`gso_run`].
+///
+/// Source address and interface selection in each `SendMeta` is passed on as a
+/// packet info control message. With `connected` set the destination addresses in
+/// `queue` are ignored and every message goes
+/// to the socket's connected peer.
+///
+/// For each message sent, the number of datagrams it held and the number of bytes the
+/// kernel took are pushed onto `sent` in queue order. Datagrams that weren't sent are
+/// left for a following call.
+pub(crate) fn send_mmsg(
+    socket: &UdpSocket,
+    queue: &VecDeque<(Bytes, SendMeta)>,
+    gso: bool,
+    max_segment: usize,
+    connected: bool,
+    sent: &mut Vec<(usize, usize)>,
+) -> io::Result<()> {
+    // (first datagram, number of datagrams, segment size) for each message
+    let mut runs = Vec::new();
+    let mut start = 0;
+    while start < queue.len() && runs.len() < MAX_MMSG {
+        let count = if gso {
+            gso_run(queue, start, max_segment)
+        } else {
+            1
+        };
+        runs.push((start, count, queue[start].0.len()));
+        start += count;
+    }
+
+    let mut addrs = runs
+        .iter()
+        .map(|&(start, _, _)| super::addr_to_sockaddr(&queue[start].1.addr))
+        .collect::<Vec<_>>();
+    let mut iovs = queue
+        .iter()
+        .take(start)
+        .map(|(datagram, _)| libc::iovec {
+            iov_base: datagram.as_ptr() as *mut libc::c_void,
+            iov_len: datagram.len(),
+        })
+        .collect::<Vec<_>>();
+    let mut cmsgs = vec![CmsgBuf::default(); runs.len()];
+    let mut hdrs = Vec::with_capacity(runs.len());
+    for (i, &(start, count, segment)) in runs.iter().enumerate() {
+        // SAFETY: an all-zero `mmsghdr` is a valid value
+        let mut hdr: libc::mmsghdr = unsafe { mem::zeroed() };
+        if !connected {
+            let (addr, len) = &mut addrs[i];
+            hdr.msg_hdr.msg_name = addr as *mut _ as *mut libc::c_void;
+            hdr.msg_hdr.msg_namelen = *len;
+        }
+        hdr.msg_hdr.msg_iov = &mut iovs[start];
+        hdr.msg_hdr.msg_iovlen = count as _;
+        // SAFETY: `cmsgs` outlives the `sendmmsg` call
+        let mut encoder = unsafe { cmsg::Encoder::new(&mut hdr.msg_hdr, &mut cmsgs[i]) };
+        if count > 1 {
+            encoder.push(libc::SOL_UDP, libc::UDP_SEGMENT, segment as u16);
+        }
+        super::encode_send_cmsg(&mut encoder, &queue[start].1);
+        encoder.finish();
+        hdrs.push(hdr);
+    }
+
+    // SAFETY: every header points at live iovecs, sockaddr_storage and control buffers
+    // for the duration of the call, the kernel only reads from the datagram buffers
+    let n = unsafe { libc::sendmmsg(socket.as_raw_fd(), hdrs.as_mut_ptr(), hdrs.len() as _, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    sent.clear();
+    sent.extend(
+        runs.iter()
+            .zip(hdrs.iter())
+            .take(n as usize)
+            .map(|(&(_, count, _), hdr)| (count, hdr.msg_len as usize)),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+//! Socket calls that tokio's `UdpSocket` doesn't expose directly.
+#[cfg(unix)]
+mod cmsg;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(unix)]
+mod unix;
+
+use crate::meta::RecvMeta;
+#[cfg(not(unix))]
+use crate::meta::SendMeta;
+
+#[cfg(target_os = "linux")]
+pub(crate) use linux::{gso_run, gso_supported, recv_mmsg, send_mmsg, set_gro};
+#[cfg(unix)]
+pub(crate) use unix::{
+    addr_to_sockaddr, decode_recv_cmsg, encode_send_cmsg, poll_io, poll_recv_msg, poll_send_msg,
+    set_multicast_hops_v6, set_multicast_if_v4, set_multicast_if_v6, set_pktinfo, set_recv_ecn,
+    set_recv_timestamps, set_source_membership_v4, setsockopt, sockaddr_to_addr,
+};
+
+/// Whether `err` was caused by an ICMP error for an earlier send rather than by the
+/// receive itself. Windows reports port unreachable as `ConnectionReset`.
+pub(crate) fn is_icmp_error(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset
+    ) || is_unreachable(err)
+}
+
+/// The unreachable `ErrorKind`s need Rust 1.83, compare the OS error codes instead.
+#[cfg(unix)]
+fn is_unreachable(err: &std::io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EHOSTUNREACH) | Some(libc::ENETUNREACH)
+    )
+}
+
+/// `WSAEHOSTUNREACH` and `WSAENETUNREACH`.
+#[cfg(windows)]
+fn is_unreachable(err: &std::io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(10065) | Some(10051))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_unreachable(_err: &std::io::Error) -> bool {
+    false
+}
+
+/// What the kernel told us about a received datagram besides its contents.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RecvInfo {
+    pub(crate) meta: RecvMeta,
+    /// The datagram was larger than the buffer it was received into.
+    pub(crate) truncated: bool,
+}
+
+/// Platforms without `recvmsg` fall back to `poll_recv_from`, which can't detect
+/// truncation.
+#[cfg(not(unix))]
+pub(crate) fn poll_recv_msg(
+    socket: &tokio::net::UdpSocket,
+    cx: &mut std::task::Context<'_>,
+    buf: &mut [std::mem::MaybeUninit<u8>],
+) -> std::task::Poll<std::io::Result<(usize, RecvInfo)>> {
+    let mut read = tokio::io::ReadBuf::uninit(buf);
+    let addr = futures_core::ready!(socket.poll_recv_from(cx, &mut read))?;
+    let info = RecvInfo {
+        meta: RecvMeta::new(addr),
+        truncated: false,
+    };
+    std::task::Poll::Ready(Ok((read.filled().len(), info)))
+}
+
+/// Control messages on sent datagrams need `sendmsg`, without it the options in
+/// `meta` are dropped and the datagram is sent plainly.
+#[cfg(not(unix))]
+pub(crate) fn poll_send_msg(
+    socket: &tokio::net::UdpSocket,
+    cx: &mut std::task::Context<'_>,
+    buf: &[u8],
+    meta: &SendMeta,
+    connected: bool,
+) -> std::task::Poll<std::io::Result<usize>> {
+    if connected {
+        socket.poll_send(cx, buf)
+    } else {
+        socket.poll_send_to(cx, buf, meta.addr)
+    }
+}
+
+/// Packet info control messages need `recvmsg`.
+#[cfg(not(unix))]
+pub(crate) fn set_pktinfo(_socket: &tokio::net::UdpSocket, _enabled: bool) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "packet info is not supported on this platform",
+    ))
+}
+
+/// ECN control messages need `recvmsg`.
+#[cfg(not(unix))]
+pub(crate) fn set_recv_ecn(_socket: &tokio::net::UdpSocket, _enabled: bool) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "ECN is not supported on this platform",
+    ))
+}
+
+/// Receive timestamps are reported in control messages, which need `recvmsg`.
+#[cfg(not(unix))]
+pub(crate) fn set_recv_timestamps(
+    _socket: &tokio::net::UdpSocket,
+    _enabled: bool,
+) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "receive timestamps are not supported on this platform",
+    ))
+}
+
+/// Multicast options beyond what `UdpSocket` has are set with `setsockopt`.
+#[cfg(not(unix))]
+fn multicast_unsupported() -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "this multicast option is not supported on this platform",
+    ))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_source_membership_v4(
+    _socket: &tokio::net::UdpSocket,
+    _group: std::net::Ipv4Addr,
+    _source: std::net::Ipv4Addr,
+    _interface: std::net::Ipv4Addr,
+    _join: bool,
+) -> std::io::Result<()> {
+    multicast_unsupported()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_multicast_hops_v6(
+    _socket: &tokio::net::UdpSocket,
+    _hops: u32,
+) -> std::io::Result<()> {
+    multicast_unsupported()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_multicast_if_v4(
+    _socket: &tokio::net::UdpSocket,
+    _interface: std::net::Ipv4Addr,
+) -> std::io::Result<()> {
+    multicast_unsupported()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_multicast_if_v6(
+    _socket: &tokio::net::UdpSocket,
+    _ifindex: u32,
+) -> std::io::Result<()> {
+    multicast_unsupported()
+}
+
+/// UDP segmentation offload is only available on Linux.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn gso_supported(_socket: &tokio::net::UdpSocket) -> bool {
+    false
+}
+
+/// UDP generic receive offload is only available on Linux.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_gro(_socket: &tokio::net::UdpSocket, _enabled: bool) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "UDP generic receive offload is not supported on this platform",
+    ))
+}
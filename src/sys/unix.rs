@@ -0,0 +1,465 @@
+#[cfg(target_os = "linux")]
+use std::time::{Duration, SystemTime};
+use std::{
+    io,
+    mem::{self, MaybeUninit},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::unix::io::AsRawFd,
+    task::{Context, Poll},
+};
+
+use futures_core::ready;
+use tokio::{io::Interest, net::UdpSocket};
+
+use super::{
+    cmsg::{self, CmsgBuf},
+    RecvInfo,
+};
+use crate::meta::{EcnCodepoint, RecvMeta, SendMeta};
+
+/// Run a non-blocking syscall `f` on `socket` once it is ready for `interest`,
+/// registering for wakeup if it would block.
+pub(crate) fn poll_io<R>(
+    socket: &UdpSocket,
+    cx: &mut Context<'_>,
+    interest: Interest,
+    mut f: impl FnMut() -> io::Result<R>,
+) -> Poll<io::Result<R>> {
+    loop {
+        if interest == Interest::READABLE {
+            ready!(socket.poll_recv_ready(cx))?;
+        } else {
+            ready!(socket.poll_send_ready(cx))?;
+        }
+        match socket.try_io(interest, &mut f) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            res => return Poll::Ready(res),
+        }
+    }
+}
+
+/// Convert a `sockaddr_storage` filled in by the kernel into a `SocketAddr`.
+pub(crate) fn sockaddr_to_addr(
+    storage: &libc::sockaddr_storage,
+    len: libc::socklen_t,
+) -> io::Result<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET if len as usize >= mem::size_of::<libc::sockaddr_in>() => {
+            // SAFETY: `ss_family` is `AF_INET` so the storage holds a `sockaddr_in`
+            let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+            Ok(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        libc::AF_INET6 if len as usize >= mem::size_of::<libc::sockaddr_in6>() => {
+            // SAFETY: `ss_family` is `AF_INET6` so the storage holds a `sockaddr_in6`
+            let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid socket address family",
+        )),
+    }
+}
+
+/// Convert a `SocketAddr` into a `sockaddr_storage` and its length for passing to the kernel.
+pub(crate) fn addr_to_sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    // SAFETY: an all-zero `sockaddr_storage` is a valid value
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            // SAFETY: `sockaddr_storage` is large enough and aligned for any address type
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr = libc::in_addr {
+                s_addr: u32::from(*addr.ip()).to_be(),
+            };
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            // SAFETY: `sockaddr_storage` is large enough and aligned for any address type
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr = libc::in6_addr {
+                s6_addr: addr.ip().octets(),
+            };
+            sin6.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+/// Set a socket option on `socket`.
+pub(crate) fn setsockopt<T: Copy>(
+    socket: &UdpSocket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: T,
+) -> io::Result<()> {
+    // SAFETY: `value` is valid for the size passed
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Receive a single datagram into `buf` with `recvmsg` once the socket is readable.
+pub(crate) fn poll_recv_msg(
+    socket: &UdpSocket,
+    cx: &mut Context<'_>,
+    buf: &mut [MaybeUninit<u8>],
+) -> Poll<io::Result<(usize, RecvInfo)>> {
+    poll_io(socket, cx, Interest::READABLE, || recv_msg(socket, buf))
+}
+
+fn recv_msg(socket: &UdpSocket, buf: &mut [MaybeUninit<u8>]) -> io::Result<(usize, RecvInfo)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // SAFETY: an all-zero `sockaddr_storage` is a valid value
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut cmsg = CmsgBuf::default();
+    // SAFETY: an all-zero `msghdr` is a valid value
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    hdr.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+    hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = cmsg.0.as_mut_ptr() as *mut libc::c_void;
+    hdr.msg_controllen = super::cmsg::CMSG_LEN as _;
+
+    // SAFETY: `hdr` points at a live iovec, sockaddr_storage and control buffer for the
+    // duration of the call, the kernel only writes initialized bytes into `buf`
+    let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut hdr, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut info = RecvInfo {
+        meta: RecvMeta::new(sockaddr_to_addr(&addr, hdr.msg_namelen)?),
+        truncated: hdr.msg_flags & libc::MSG_TRUNC != 0,
+    };
+    // SAFETY: the control buffer was filled in by the kernel
+    for cmsg in unsafe { cmsg::Iter::new(&hdr) } {
+        decode_recv_cmsg(cmsg, &mut info.meta);
+    }
+    Ok((n as usize, info))
+}
+
+/// Send a single datagram with `sendmsg` once the socket is writable, attaching the
+/// control messages `meta` asks for.
+pub(crate) fn poll_send_msg(
+    socket: &UdpSocket,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+    meta: &SendMeta,
+    connected: bool,
+) -> Poll<io::Result<usize>> {
+    poll_io(socket, cx, Interest::WRITABLE, || {
+        send_msg(socket, buf, meta, connected)
+    })
+}
+
+fn send_msg(socket: &UdpSocket, buf: &[u8], meta: &SendMeta, connected: bool) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let (mut addr, addr_len) = addr_to_sockaddr(&meta.addr);
+    let mut cmsg = CmsgBuf::default();
+    // SAFETY: an all-zero `msghdr` is a valid value
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    if !connected {
+        hdr.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+        hdr.msg_namelen = addr_len;
+    }
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    // SAFETY: `cmsg` outlives the `sendmsg` call
+    let mut encoder = unsafe { cmsg::Encoder::new(&mut hdr, &mut cmsg) };
+    encode_send_cmsg(&mut encoder, meta);
+    encoder.finish();
+
+    // SAFETY: `hdr` points at a live iovec, sockaddr_storage and control buffer for the
+    // duration of the call, the kernel only reads from `buf`
+    let n = unsafe { libc::sendmsg(socket.as_raw_fd(), &hdr, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(n as usize)
+}
+
+/// Push the control messages `meta` asks for onto a datagram being sent. Options the
+/// platform doesn't support are left out.
+#[cfg_attr(
+    not(any(target_os = "linux", target_os = "macos", target_os = "ios")),
+    allow(unused_variables)
+)]
+pub(crate) fn encode_send_cmsg(encoder: &mut cmsg::Encoder<'_>, meta: &SendMeta) {
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "ios"))]
+    if meta.src_ip.is_some() || meta.ifindex.is_some() {
+        encode_pktinfo(encoder, meta);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(ecn) = meta.ecn {
+        let bits = ecn.bits() as libc::c_int;
+        if meta.addr.is_ipv4() {
+            encoder.push(libc::IPPROTO_IP, libc::IP_TOS, bits);
+        } else {
+            encoder.push(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, bits);
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "ios"))]
+fn encode_pktinfo(encoder: &mut cmsg::Encoder<'_>, meta: &SendMeta) {
+    let ifindex = meta.ifindex.unwrap_or(0);
+    // the source address decides the family, falling back to the destination's
+    let v4 = match meta.src_ip {
+        Some(ip) => ip.is_ipv4(),
+        None => meta.addr.is_ipv4(),
+    };
+    if v4 {
+        // SAFETY: an all-zero `in_pktinfo` is a valid value
+        let mut info: libc::in_pktinfo = unsafe { mem::zeroed() };
+        info.ipi_ifindex = ifindex as _;
+        if let Some(IpAddr::V4(ip)) = meta.src_ip {
+            info.ipi_spec_dst = libc::in_addr {
+                s_addr: u32::from(ip).to_be(),
+            };
+        }
+        encoder.push(libc::IPPROTO_IP, libc::IP_PKTINFO, info);
+    } else {
+        // SAFETY: an all-zero `in6_pktinfo` is a valid value
+        let mut info: libc::in6_pktinfo = unsafe { mem::zeroed() };
+        info.ipi6_ifindex = ifindex;
+        if let Some(IpAddr::V6(ip)) = meta.src_ip {
+            info.ipi6_addr = libc::in6_addr {
+                s6_addr: ip.octets(),
+            };
+        }
+        encoder.push(libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, info);
+    }
+}
+
+/// Fill in `meta` from a control message received with a datagram, messages that
+/// aren't recognised are ignored.
+// `ipi_ifindex` is a `c_int` on Linux and a `c_uint` on Apple platforms
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn decode_recv_cmsg(cmsg: &libc::cmsghdr, meta: &mut RecvMeta) {
+    match (cmsg.cmsg_level, cmsg.cmsg_type) {
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "ios"))]
+        (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+            // SAFETY: `IP_PKTINFO` messages hold an `in_pktinfo`
+            let info = unsafe { cmsg::decode::<libc::in_pktinfo>(cmsg) };
+            let dst = Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr));
+            // `ipi_spec_dst` is the local address the kernel would send a reply from,
+            // which differs from the destination for multicast and broadcast
+            let local = Ipv4Addr::from(u32::from_be(info.ipi_spec_dst.s_addr));
+            meta.dst_ip = Some(IpAddr::V4(dst));
+            meta.local_ip = if !local.is_unspecified() {
+                Some(IpAddr::V4(local))
+            } else if dst.is_multicast() || dst.is_broadcast() {
+                None
+            } else {
+                Some(IpAddr::V4(dst))
+            };
+            meta.ifindex = Some(info.ipi_ifindex as u32);
+        }
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "ios"))]
+        (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+            // SAFETY: `IPV6_PKTINFO` messages hold an `in6_pktinfo`
+            let info = unsafe { cmsg::decode::<libc::in6_pktinfo>(cmsg) };
+            let dst = Ipv6Addr::from(info.ipi6_addr.s6_addr);
+            meta.dst_ip = Some(IpAddr::V6(dst));
+            meta.local_ip = if dst.is_multicast() {
+                None
+            } else {
+                Some(IpAddr::V6(dst))
+            };
+            meta.ifindex = Some(info.ipi6_ifindex);
+        }
+        #[cfg(target_os = "linux")]
+        (libc::IPPROTO_IP, libc::IP_TOS) => {
+            // SAFETY: `IP_TOS` messages hold the TOS byte
+            let tos = unsafe { cmsg::decode::<u8>(cmsg) };
+            meta.ecn = EcnCodepoint::from_bits(tos);
+        }
+        #[cfg(target_os = "linux")]
+        (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+            // SAFETY: `IPV6_TCLASS` messages hold the traffic class as a `c_int`
+            let tclass = unsafe { cmsg::decode::<libc::c_int>(cmsg) };
+            meta.ecn = EcnCodepoint::from_bits(tclass as u8);
+        }
+        #[cfg(target_os = "linux")]
+        (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+            // SAFETY: `SCM_TIMESTAMPNS` messages hold a `timespec`
+            let ts = unsafe { cmsg::decode::<libc::timespec>(cmsg) };
+            let since_epoch = Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32);
+            meta.timestamp = Some(SystemTime::UNIX_EPOCH + since_epoch);
+        }
+        _ => {}
+    }
+}
+
+/// Ask the kernel to report the destination address and interface of each datagram
+/// received on `socket` (`IP_PKTINFO` / `IPV6_RECVPKTINFO`).
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "ios"))]
+pub(crate) fn set_pktinfo(socket: &UdpSocket, enabled: bool) -> io::Result<()> {
+    let enabled = enabled as libc::c_int;
+    match socket.local_addr()? {
+        SocketAddr::V4(_) => setsockopt(socket, libc::IPPROTO_IP, libc::IP_PKTINFO, enabled),
+        SocketAddr::V6(_) => {
+            setsockopt(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, enabled)
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "ios")))]
+pub(crate) fn set_pktinfo(_socket: &UdpSocket, _enabled: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "packet info is not supported on this platform",
+    ))
+}
+
+/// Ask the kernel to report the ECN codepoint of each datagram received on `socket`
+/// (`IP_RECVTOS` / `IPV6_RECVTCLASS`).
+#[cfg(target_os = "linux")]
+pub(crate) fn set_recv_ecn(socket: &UdpSocket, enabled: bool) -> io::Result<()> {
+    let enabled = enabled as libc::c_int;
+    match socket.local_addr()? {
+        SocketAddr::V4(_) => setsockopt(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, enabled),
+        SocketAddr::V6(_) => {
+            setsockopt(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, enabled)?;
+            // IPv4 traffic on a dual-stack socket is reported with `IP_TOS`, this fails
+            // harmlessly on `IPV6_V6ONLY` sockets
+            let _ = setsockopt(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, enabled);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_recv_ecn(_socket: &UdpSocket, _enabled: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "ECN is not supported on this platform",
+    ))
+}
+
+/// Ask the kernel to timestamp each datagram received on `socket` (`SO_TIMESTAMPNS`).
+#[cfg(target_os = "linux")]
+pub(crate) fn set_recv_timestamps(socket: &UdpSocket, enabled: bool) -> io::Result<()> {
+    setsockopt(
+        socket,
+        libc::SOL_SOCKET,
+        libc::SO_TIMESTAMPNS,
+        enabled as libc::c_int,
+    )
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_recv_timestamps(_socket: &UdpSocket, _enabled: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "receive timestamps are not supported on this platform",
+    ))
+}
+
+/// Join or leave the source-specific multicast `group`, receiving only what `source`
+/// sends to it, on the interface with address `interface` (`IP_ADD_SOURCE_MEMBERSHIP`
+/// / `IP_DROP_SOURCE_MEMBERSHIP`).
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+))]
+pub(crate) fn set_source_membership_v4(
+    socket: &UdpSocket,
+    group: Ipv4Addr,
+    source: Ipv4Addr,
+    interface: Ipv4Addr,
+    join: bool,
+) -> io::Result<()> {
+    let in_addr = |ip: Ipv4Addr| libc::in_addr {
+        s_addr: u32::from(ip).to_be(),
+    };
+    let mreq = libc::ip_mreq_source {
+        imr_multiaddr: in_addr(group),
+        imr_sourceaddr: in_addr(source),
+        imr_interface: in_addr(interface),
+    };
+    let name = if join {
+        libc::IP_ADD_SOURCE_MEMBERSHIP
+    } else {
+        libc::IP_DROP_SOURCE_MEMBERSHIP
+    };
+    setsockopt(socket, libc::IPPROTO_IP, name, mreq)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+pub(crate) fn set_source_membership_v4(
+    _socket: &UdpSocket,
+    _group: Ipv4Addr,
+    _source: Ipv4Addr,
+    _interface: Ipv4Addr,
+    _join: bool,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "source-specific multicast is not supported on this platform",
+    ))
+}
+
+/// Set the hop limit of multicast datagrams sent on `socket` (`IPV6_MULTICAST_HOPS`).
+pub(crate) fn set_multicast_hops_v6(socket: &UdpSocket, hops: u32) -> io::Result<()> {
+    let hops = hops as libc::c_int;
+    setsockopt(socket, libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_HOPS, hops)
+}
+
+/// Send multicast datagrams out of the interface with address `interface`
+/// (`IP_MULTICAST_IF`).
+pub(crate) fn set_multicast_if_v4(socket: &UdpSocket, interface: Ipv4Addr) -> io::Result<()> {
+    let interface = libc::in_addr {
+        s_addr: u32::from(interface).to_be(),
+    };
+    setsockopt(socket, libc::IPPROTO_IP, libc::IP_MULTICAST_IF, interface)
+}
+
+/// Send multicast datagrams out of the interface with index `ifindex`
+/// (`IPV6_MULTICAST_IF`).
+pub(crate) fn set_multicast_if_v6(socket: &UdpSocket, ifindex: u32) -> io::Result<()> {
+    let ifindex = ifindex as libc::c_int;
+    setsockopt(socket, libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_IF, ifindex)
+}
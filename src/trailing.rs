@@ -0,0 +1,44 @@
+use bytes::BytesMut;
+
+use std::{fmt, net::SocketAddr};
+
+/// What to do with bytes left over in a datagram once the decoder stops producing
+/// frames from it.
+///
+/// Each datagram is decoded on its own, when the decoder returns `None` any bytes it
+/// didn't consume can never become part of a frame since the next datagram starts a
+/// fresh buffer.
+#[derive(Default)]
+pub enum TrailingData {
+    /// Silently drop the leftover bytes. This is the default.
+    #[default]
+    Discard,
+    /// Yield an `io::Error` with kind `InvalidData` wrapping a [`TrailingBytes`] from
+    /// the `Stream`, converted into the codec's error type. The leftover bytes are
+    /// dropped and the next call to `poll_next` carries on with the next datagram.
+    ///
+    /// [`TrailingBytes`]: crate::TrailingBytes
+    Error,
+    /// Hand the leftover bytes and the address of the sender to a callback.
+    Callback(Box<dyn FnMut(BytesMut, SocketAddr) + Send>),
+}
+
+impl TrailingData {
+    /// Create a `TrailingData::Callback` from a closure.
+    pub fn callback<F>(f: F) -> Self
+    where
+        F: FnMut(BytesMut, SocketAddr) + Send + 'static,
+    {
+        TrailingData::Callback(Box::new(f))
+    }
+}
+
+impl fmt::Debug for TrailingData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrailingData::Discard => f.write_str("Discard"),
+            TrailingData::Error => f.write_str("Error"),
+            TrailingData::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
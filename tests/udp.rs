@@ -1,16 +1,26 @@
 #![warn(rust_2018_idioms)]
 
-use tokio_udp_framed::{UdpFramed, UdpFramedRecv, UdpFramedSend};
+use tokio_udp_framed::{
+    Correlate, DatagramDecoder, DatagramEncoder, DatagramTooLarge, DatagramTruncated, Discovery,
+    EcnCodepoint, RecvMeta, Resilient, SendMeta, TrailingBytes, TrailingData, TypedErrors,
+    UdpClient, UdpDatagrams, UdpFramed, UdpFramedBuilder, UdpFramedConnected,
+    UdpFramedConnectedRecv, UdpFramedConnectedSend, UdpFramedError, UdpFramedMux, UdpFramedRecv,
+    UdpFramedSend,
+};
 
 use tokio::net::UdpSocket;
 use tokio_stream::StreamExt;
-use tokio_util::codec::{Decoder, Encoder, LinesCodec};
+use tokio_util::codec::{BytesCodec, Decoder, Encoder, LinesCodec, LinesCodecError};
 
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use futures::future::try_join;
 use futures::future::FutureExt;
 use futures::sink::SinkExt;
-use std::{io, sync::Arc};
+use std::{
+    io,
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+};
 
 #[cfg_attr(any(target_os = "macos", target_os = "ios"), allow(unused_assignments))]
 #[tokio::test]
@@ -191,3 +201,1581 @@ async fn framed_half() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn send_queue_separate_datagrams() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let c_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+    let c_addr = c_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+    let mut b = UdpFramedRecv::new(b_soc, LinesCodec::new());
+    let mut c = UdpFramedRecv::new(c_soc, LinesCodec::new());
+
+    // queue several items before flushing, each should be its own datagram
+    a.feed((&b"one\n"[..], b_addr)).await?;
+    a.feed((&b"two\n"[..], c_addr)).await?;
+    a.feed((&b"three\n"[..], b_addr)).await?;
+    a.flush().await?;
+
+    assert_eq!(
+        b.next().await.unwrap().unwrap(),
+        ("one".to_string(), a_addr)
+    );
+    assert_eq!(
+        b.next().await.unwrap().unwrap(),
+        ("three".to_string(), a_addr)
+    );
+    assert_eq!(
+        c.next().await.unwrap().unwrap(),
+        ("two".to_string(), a_addr)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn send_queue_high_water_mark() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramed::new(a_soc, ByteCodec);
+    let mut b = UdpFramed::new(b_soc, LinesCodec::new());
+    a.set_send_high_water_mark(2);
+
+    // feeding past the high-water mark flushes the queue without an explicit flush
+    a.feed((&b"1\n"[..], b_addr)).await?;
+    a.feed((&b"2\n"[..], b_addr)).await?;
+    a.feed((&b"3\n"[..], b_addr)).await?;
+
+    assert_eq!(b.next().await.unwrap().unwrap(), ("1".to_string(), a_addr));
+    assert_eq!(b.next().await.unwrap().unwrap(), ("2".to_string(), a_addr));
+
+    a.close().await?;
+    assert_eq!(b.next().await.unwrap().unwrap(), ("3".to_string(), a_addr));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn recv_batch() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let c_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+    let c_addr = c_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+    let mut b = UdpFramedRecv::new(b_soc, LinesCodec::new());
+    let mut c = UdpFramedSend::new(c_soc, ByteCodec);
+    b.set_recv_batch_size(4);
+    assert_eq!(b.recv_batch_size(), 4);
+
+    for i in 0..6 {
+        a.feed((format!("a{}\n", i).as_bytes(), b_addr)).await?;
+    }
+    a.flush().await?;
+    c.send((&b"c0\nc1\n"[..], b_addr)).await?;
+
+    for i in 0..6 {
+        assert_eq!(
+            b.next().await.unwrap().unwrap(),
+            (format!("a{}", i), a_addr)
+        );
+    }
+    assert_eq!(b.next().await.unwrap().unwrap(), ("c0".to_string(), c_addr));
+    assert_eq!(b.next().await.unwrap().unwrap(), ("c1".to_string(), c_addr));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn send_batch_fan_out() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let a_addr = a_soc.local_addr()?;
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+
+    let mut peers = Vec::new();
+    for _ in 0..5 {
+        let soc = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = soc.local_addr()?;
+        peers.push((UdpFramedRecv::new(soc, LinesCodec::new()), addr));
+    }
+
+    // all replies are queued and then flushed together
+    for (i, (_, addr)) in peers.iter().enumerate() {
+        a.feed((format!("reply {}\n", i).as_bytes(), *addr)).await?;
+    }
+    a.flush().await?;
+
+    for (i, (peer, _)) in peers.iter_mut().enumerate() {
+        assert_eq!(
+            peer.next().await.unwrap().unwrap(),
+            (format!("reply {}", i), a_addr)
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn send_rejected_datagram() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+    let mut b = UdpFramedRecv::new(b_soc, LinesCodec::new());
+
+    // the kernel rejects port 0, that datagram is dropped and the rest still sent
+    let rejected = "127.0.0.1:0".parse().unwrap();
+    a.feed((&b"first\n"[..], b_addr)).await?;
+    a.feed((&b"rejected\n"[..], rejected)).await?;
+    a.feed((&b"second\n"[..], b_addr)).await?;
+    let err = a.flush().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    a.flush().await?;
+
+    assert_eq!(
+        b.next().await.unwrap().unwrap(),
+        ("first".to_string(), a_addr)
+    );
+    assert_eq!(
+        b.next().await.unwrap().unwrap(),
+        ("second".to_string(), a_addr)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn send_segmentation_offload() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let c_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+    let c_addr = c_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+    // falls back to one datagram per send where GSO isn't supported
+    let enabled = a.set_send_segmentation_offload(true);
+    assert_eq!(enabled, a.send_segmentation_offload());
+
+    // with GRO on the receivers a segmented run is delivered still coalesced
+    let mut b = UdpFramedRecv::new(b_soc, BytesCodec::new());
+    let mut c = UdpFramedRecv::new(c_soc, BytesCodec::new());
+    #[cfg(target_os = "linux")]
+    {
+        b.set_recv_gro(true)?;
+        c.set_recv_gro(true)?;
+    }
+    let (mut b, mut c) = (b.with_meta(), c.with_meta());
+
+    // a run of same-size frames with a shorter one at the end, then a frame for
+    // another peer and another run
+    let mut sent = Vec::new();
+    for i in 0..10u8 {
+        sent.push((vec![i; 100], b_addr));
+    }
+    sent.push((vec![10; 40], b_addr));
+    sent.push((vec![11; 100], c_addr));
+    sent.push((vec![12; 100], b_addr));
+    sent.push((vec![13; 100], b_addr));
+
+    for (data, addr) in &sent {
+        a.feed((&data[..], *addr)).await?;
+    }
+    a.flush().await?;
+
+    // every frame arrives as its own datagram, all but the one for `c` as a segment
+    for (i, (data, addr)) in sent.iter().enumerate() {
+        let peer = if *addr == b_addr { &mut b } else { &mut c };
+        let (frame, meta) = peer.next().await.unwrap()?;
+        assert_eq!((&frame[..], meta.addr), (&data[..], a_addr));
+        let segmented = enabled && i != 11;
+        assert_eq!(meta.segment_size, Some(100).filter(|_| segmented));
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn send_segmentation_offload_mtu() -> std::io::Result<()> {
+    // a documentation address, only reachable through an interface with a real MTU
+    let far: std::net::SocketAddr = "198.51.100.1:9".parse().unwrap();
+    let a_soc = UdpSocket::bind("0.0.0.0:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    if a_soc.connect(far).await.is_err() {
+        // no route to test with
+        return Ok(());
+    }
+    let a_soc = UdpSocket::bind("0.0.0.0:0").await?;
+
+    let a_addr = (Ipv4Addr::LOCALHOST, a_soc.local_addr()?.port()).into();
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+    if !a.set_send_segmentation_offload(true) {
+        return Ok(());
+    }
+
+    // segments larger than the MTU can't be offloaded, they are sent one by one
+    for _ in 0..3 {
+        a.feed((&[0; 16_000][..], far)).await?;
+    }
+    a.flush().await?;
+    assert!(a.send_segmentation_offload());
+
+    // smaller runs are still sent
+    for i in 0..4u8 {
+        a.feed((&[i; 100][..], b_addr)).await?;
+    }
+    a.flush().await?;
+    let mut buf = [0; 1024];
+    for i in 0..4u8 {
+        let (len, from) = b_soc.recv_from(&mut buf).await?;
+        assert_eq!((&buf[..len], from), (&[i; 100][..], a_addr));
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn recv_gro() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+    // `LinesCodec` hands back everything left in a datagram without a delimiter, so
+    // segments that weren't split apart would show up as one long line
+    let mut b = UdpFramedRecv::new(b_soc, LinesCodec::new());
+    b.set_recv_gro(true)?;
+    assert!(b.recv_gro());
+    let mut b = b.with_meta();
+    // datagrams sent with GSO reach the receiver coalesced
+    let coalesced = a.set_send_segmentation_offload(true);
+
+    for i in 0..8 {
+        a.feed((format!("gro{}", i).as_bytes(), b_addr)).await?;
+    }
+    a.feed((&b"end"[..], b_addr)).await?;
+    a.flush().await?;
+
+    let expected = (0..8)
+        .map(|i| format!("gro{}", i))
+        .chain(Some("end".to_string()));
+    for line in expected {
+        let (got, meta): (String, RecvMeta) = b.next().await.unwrap().unwrap();
+        assert_eq!((got, meta.addr), (line, a_addr));
+        // the whole run was read at once and split at the segment size
+        assert_eq!(meta.segment_size, Some(4).filter(|_| coalesced));
+    }
+
+    Ok(())
+}
+
+/// Decodes fixed 4 byte frames and leaves anything shorter in the buffer.
+pub struct FixedCodec;
+
+impl Decoder for FixedCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Vec<u8>>, io::Error> {
+        if buf.len() < 4 {
+            return Ok(None);
+        }
+        Ok(Some(buf.split_to(4).to_vec()))
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Vec<u8>>, io::Error> {
+        self.decode(buf)
+    }
+}
+
+#[tokio::test]
+async fn trailing_data() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+    let mut b = UdpFramedRecv::new(b_soc, FixedCodec);
+
+    // discarded by default
+    a.send((&b"abcdefg"[..], b_addr)).await?;
+    a.send((&b"hijk"[..], b_addr)).await?;
+    assert_eq!(b.next().await.unwrap()?, (b"abcd".to_vec(), a_addr));
+    assert_eq!(b.next().await.unwrap()?, (b"hijk".to_vec(), a_addr));
+
+    b.set_trailing_data(TrailingData::Error);
+    a.send((&b"abcdefg"[..], b_addr)).await?;
+    a.send((&b"hijk"[..], b_addr)).await?;
+    assert_eq!(b.next().await.unwrap()?, (b"abcd".to_vec(), a_addr));
+    let err = b.next().await.unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(b.next().await.unwrap()?, (b"hijk".to_vec(), a_addr));
+
+    let leftovers = Arc::new(Mutex::new(Vec::new()));
+    let cb_leftovers = leftovers.clone();
+    b.set_trailing_data(TrailingData::callback(move |buf, addr| {
+        cb_leftovers.lock().unwrap().push((buf.to_vec(), addr));
+    }));
+    a.send((&b"abcdefg"[..], b_addr)).await?;
+    a.send((&b"hijk"[..], b_addr)).await?;
+    assert_eq!(b.next().await.unwrap()?, (b"abcd".to_vec(), a_addr));
+    assert_eq!(b.next().await.unwrap()?, (b"hijk".to_vec(), a_addr));
+    assert_eq!(*leftovers.lock().unwrap(), vec![(b"efg".to_vec(), a_addr)]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn recv_truncated() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+    let mut b = UdpFramedRecv::new(b_soc, FixedCodec);
+    b.set_recv_max_datagram_size(8);
+
+    for batch_size in [1, 4] {
+        b.set_recv_batch_size(batch_size);
+
+        a.send((&b"abcdefghijkl"[..], b_addr)).await?;
+        a.send((&b"wxyz"[..], b_addr)).await?;
+
+        let err = b.next().await.unwrap().unwrap_err();
+        let truncated = err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<DatagramTruncated>())
+            .expect("truncation error");
+        assert_eq!(truncated.addr, a_addr);
+        assert_eq!(truncated.max_datagram_size, 8);
+
+        assert_eq!(b.next().await.unwrap().unwrap(), (b"wxyz".to_vec(), a_addr));
+    }
+
+    // a size of 0 leaves room for one byte
+    b.set_recv_max_datagram_size(0);
+    assert_eq!(b.recv_max_datagram_size(), 1);
+    a.send((&b"ab"[..], b_addr)).await?;
+    let err = b.next().await.unwrap().unwrap_err();
+    assert!(err.get_ref().unwrap().is::<DatagramTruncated>());
+    let mut b = UdpFramedBuilder::new()
+        .recv_max_datagram_size(0)
+        .build_recv(b.into_inner(), FixedCodec);
+    assert_eq!(b.recv_max_datagram_size(), 1);
+    b.set_recv_batch_size(4);
+
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "ios"))]
+#[tokio::test]
+async fn recv_pktinfo() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("0.0.0.0:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = ([127, 0, 0, 1], b_soc.local_addr()?.port()).into();
+
+    let mut a = UdpFramedSend::new(a_soc, LinesCodec::new());
+    let mut b = UdpFramedRecv::new(b_soc, LinesCodec::new()).with_meta();
+
+    a.send(("1", b_addr)).await.unwrap();
+    let (line, meta): (String, RecvMeta) = b.next().await.unwrap().unwrap();
+    assert_eq!(line, "1");
+    assert_eq!(meta.addr, a_addr);
+    assert_eq!(meta.dst_ip, None);
+    assert_eq!(meta.ifindex, None);
+
+    b.get_mut().set_recv_pktinfo(true)?;
+    assert!(b.get_ref().recv_pktinfo());
+    for batch_size in [1, 4] {
+        b.get_mut().set_recv_batch_size(batch_size);
+        a.send(("2", b_addr)).await.unwrap();
+        let (line, meta) = b.next().await.unwrap().unwrap();
+        assert_eq!(line, "2");
+        assert_eq!(meta.addr, a_addr);
+        assert_eq!(meta.dst_ip, Some(b_addr.ip()));
+        assert!(meta.ifindex.is_some());
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn send_pktinfo() -> std::io::Result<()> {
+    let a_soc = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let b_soc = UdpSocket::bind("0.0.0.0:0").await?;
+
+    let a_port = a_soc.local_addr()?.port();
+    let b_port = b_soc.local_addr()?.port();
+
+    let mut a_send = UdpFramedSend::new(a_soc.clone(), LinesCodec::new()).with_meta();
+    let mut a_recv = UdpFramedRecv::new(a_soc, LinesCodec::new());
+    let mut b = UdpFramed::new(b_soc, LinesCodec::new()).with_meta();
+    b.get_mut().set_recv_pktinfo(true)?;
+
+    // the whole of 127.0.0.0/8 is routed to the loopback interface
+    let src = [127, 0, 0, 2].into();
+    let dst = ([127, 0, 0, 3], b_port).into();
+    a_send
+        .send(("1", SendMeta::new(dst).with_src_ip(src)))
+        .await
+        .unwrap();
+    let (line, meta) = b.next().await.unwrap().unwrap();
+    assert_eq!(line, "1");
+    assert_eq!(meta.addr, (src, a_port).into());
+    assert_eq!(meta.dst_ip, Some(dst.ip()));
+
+    // replies come from the address the request was sent to, batched or not
+    b.send(("2", meta.reply())).await.unwrap();
+    b.feed(("3", meta.reply())).await.unwrap();
+    b.feed(("4", meta.reply())).await.unwrap();
+    SinkExt::<(&str, SendMeta)>::flush(&mut b).await.unwrap();
+    for expected in ["2", "3", "4"] {
+        let (line, addr) = a_recv.next().await.unwrap().unwrap();
+        assert_eq!(line, expected);
+        assert_eq!(addr, dst);
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn ecn() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, LinesCodec::new()).with_meta();
+    let mut b = UdpFramedRecv::new(b_soc, LinesCodec::new()).with_meta();
+    b.get_mut().set_recv_ecn(true)?;
+    assert!(b.get_ref().recv_ecn());
+
+    let marks = [
+        Some(EcnCodepoint::Ect0),
+        None,
+        Some(EcnCodepoint::Ect1),
+        Some(EcnCodepoint::Ce),
+    ];
+    for batch_size in [1, 4] {
+        b.get_mut().set_recv_batch_size(batch_size);
+        for ecn in marks {
+            let meta = SendMeta::new(b_addr);
+            let meta = match ecn {
+                Some(ecn) => meta.with_ecn(ecn),
+                None => meta,
+            };
+            a.feed(("x", meta)).await.unwrap();
+        }
+        SinkExt::<(&str, SendMeta)>::flush(&mut a).await.unwrap();
+
+        for ecn in marks {
+            let (_, meta) = b.next().await.unwrap().unwrap();
+            assert_eq!(meta.ecn, ecn);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn recv_timestamps() -> std::io::Result<()> {
+    use std::time::{Duration, SystemTime};
+
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, LinesCodec::new());
+    let mut b = UdpFramed::new(b_soc, LinesCodec::new()).with_meta();
+
+    a.send(("1", b_addr)).await.unwrap();
+    let (_, meta) = b.next().await.unwrap().unwrap();
+    assert_eq!(meta.timestamp, None);
+
+    b.get_mut().set_recv_timestamps(true)?;
+    assert!(b.get_ref().recv_timestamps());
+    // the kernel turns on arrival timestamps from a work queue, until it has run
+    // datagrams are stamped when they are read instead
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    for batch_size in [1, 4] {
+        b.get_mut().set_recv_batch_size(batch_size);
+        let before = SystemTime::now();
+        a.send(("2", b_addr)).await.unwrap();
+        // give the datagram time to arrive before we poll, so the timestamp isn't
+        // simply when we got around to reading it
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (_, meta) = b.next().await.unwrap().unwrap();
+        let received = meta.timestamp.unwrap();
+        assert!(received >= before - Duration::from_millis(10));
+        assert!(SystemTime::now().duration_since(received).unwrap() >= Duration::from_millis(40));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn connected() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let err = UdpFramedConnectedRecv::new(b_soc.clone(), LinesCodec::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+
+    a_soc.connect(b_addr).await?;
+    b_soc.connect(a_addr).await?;
+
+    let mut a = UdpFramedConnected::new(a_soc, LinesCodec::new())?;
+    assert_eq!(a.peer_addr(), b_addr);
+    let mut b_send = UdpFramedConnectedSend::new(b_soc.clone(), LinesCodec::new())?;
+    let mut b_recv = UdpFramedConnectedRecv::new(b_soc, LinesCodec::new())?;
+
+    a.send("1").await.unwrap();
+    a.feed("2").await.unwrap();
+    a.feed("3").await.unwrap();
+    SinkExt::<&str>::flush(&mut a).await.unwrap();
+    assert_eq!(b_recv.next().await.unwrap().unwrap(), "1");
+    assert_eq!(b_recv.next().await.unwrap().unwrap(), "2");
+    assert_eq!(b_recv.next().await.unwrap().unwrap(), "3");
+
+    b_send.send("4").await.unwrap();
+    assert_eq!(a.next().await.unwrap().unwrap(), "4");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn datagrams() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+    let mut b = UdpDatagrams::new(b_soc);
+
+    for batch_size in [1, 4] {
+        b.set_recv_batch_size(batch_size);
+        a.feed((&b"abc"[..], b_addr)).await?;
+        a.feed((&b""[..], b_addr)).await?;
+        a.feed((&b"defg"[..], b_addr)).await?;
+        SinkExt::<(&[u8], _)>::flush(&mut a).await?;
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(b.next().await.unwrap()?);
+        }
+        assert_eq!(
+            received,
+            [
+                (Bytes::from_static(b"abc"), a_addr),
+                (Bytes::new(), a_addr),
+                (Bytes::from_static(b"defg"), a_addr),
+            ]
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn recv_zero_copy() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedSend::new(a_soc, ByteCodec);
+    let mut b = UdpFramedBuilder::new()
+        .recv_max_datagram_size(64)
+        .recv_zero_copy(true)
+        .build_recv(b_soc, BytesCodec::new());
+    assert!(b.recv_zero_copy());
+
+    // keep some frames alive while the read buffers are refilled from the pool
+    let mut kept = Vec::new();
+    for i in 0..64u8 {
+        let msg = [i; 40];
+        a.send((&msg[..], b_addr)).await?;
+        let (frame, _) = b.next().await.unwrap()?;
+        let frame = frame.freeze();
+        assert_eq!(frame, &msg[..]);
+        if i % 8 == 0 {
+            kept.push((i, frame));
+        }
+    }
+    for (i, frame) in kept {
+        assert_eq!(frame, &[i; 40][..]);
+    }
+
+    // polling with nothing to receive keeps the prepared buffer, and the next
+    // datagram is received into it
+    assert!(b.next().now_or_never().is_none());
+    let (ptr, capacity) = (b.read_buffer().as_ptr(), b.read_buffer().capacity());
+    for _ in 0..4 {
+        assert!(b.next().now_or_never().is_none());
+        assert_eq!(b.read_buffer().as_ptr(), ptr);
+        assert_eq!(b.read_buffer().capacity(), capacity);
+    }
+    a.send((&[0xff; 40][..], b_addr)).await?;
+    let (frame, _) = b.next().await.unwrap()?;
+    assert_eq!(frame.as_ptr(), ptr);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn send_too_large() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedBuilder::new()
+        .send_max_datagram_size(8)
+        .build(a_soc, ByteCodec);
+    assert_eq!(a.send_max_datagram_size(), 8);
+    let mut b = UdpFramedRecv::new(b_soc, FixedCodec);
+
+    let err = a.send((&b"abcdefghi"[..], b_addr)).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let too_large = err.get_ref().unwrap().downcast_ref::<DatagramTooLarge>();
+    assert_eq!(
+        too_large,
+        Some(&DatagramTooLarge {
+            len: 9,
+            max_datagram_size: 8,
+        })
+    );
+    assert!(a.write_buffer().is_empty());
+
+    a.send((&b"abcd"[..], b_addr)).await?;
+    assert_eq!(b.next().await.unwrap()?, (b"abcd".to_vec(), a_addr));
+
+    // the default limit is the largest UDP payload over IPv4
+    a.set_send_max_datagram_size(65_507);
+    let err = a.send((&[0; 65_508][..], b_addr)).await.unwrap_err();
+    assert!(err.get_ref().unwrap().is::<DatagramTooLarge>());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn builder() -> std::io::Result<()> {
+    let a_soc = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedBuilder::new()
+        .write_buffer(BytesMut::with_capacity(128))
+        .send_high_water_mark(4)
+        .build_send(a_soc.clone(), ByteCodec);
+    assert_eq!(a.send_high_water_mark(), 4);
+
+    let mut b = UdpFramedBuilder::new()
+        .read_capacity(1024)
+        .read_buffer(BytesMut::from(&b"stale"[..]))
+        .recv_max_datagram_size(16)
+        .trailing_data(TrailingData::Error)
+        .build_recv(b_soc, FixedCodec);
+    assert_eq!(b.recv_max_datagram_size(), 16);
+    assert!(b.read_buffer().is_empty());
+    assert!(b.read_buffer().capacity() >= 1024);
+
+    a.send((&b"abcdefghijklmnopq"[..], b_addr)).await?;
+    a.send((&b"abcdef"[..], b_addr)).await?;
+
+    let err = b.next().await.unwrap().unwrap_err();
+    assert!(err.get_ref().unwrap().is::<DatagramTruncated>());
+    assert_eq!(b.next().await.unwrap()?, (b"abcd".to_vec(), a_addr));
+    let err = b.next().await.unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let framed = UdpFramedBuilder::new()
+        .write_capacity(32)
+        .build(a_soc, LinesCodec::new());
+    assert!(framed.write_buffer().capacity() >= 32);
+    assert_eq!(framed.recv_max_datagram_size(), 64 * 1024);
+
+    // in zero-copy mode every fresh read buffer gets the read capacity
+    let c_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let c_addr = c_soc.local_addr()?;
+    let mut c = UdpFramedBuilder::new()
+        .read_capacity(4096)
+        .recv_max_datagram_size(16)
+        .recv_zero_copy(true)
+        .build_recv(c_soc, BytesCodec::new());
+    let mut fresh = 0;
+    for _ in 0..300 {
+        a.send((&[0; 16][..], c_addr)).await?;
+        c.next().await.unwrap()?;
+        // polling prepares the buffer for the next datagram
+        assert!(c.next().now_or_never().is_none());
+        if c.read_buffer().capacity() == 4096 {
+            fresh += 1;
+        }
+    }
+    assert_eq!(fresh, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn typed_errors() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramedBuilder::new()
+        .send_max_datagram_size(12)
+        .build_send(a_soc, TypedErrors::new(ByteCodec));
+    let mut b = UdpFramedBuilder::new()
+        .recv_max_datagram_size(8)
+        .trailing_data(TrailingData::Error)
+        .build_recv(b_soc, TypedErrors::new(FixedCodec));
+
+    let err = a.send((&b"abcdefghijklm"[..], b_addr)).await.unwrap_err();
+    // the message isn't repeated further down the error chain
+    assert!(std::error::Error::source(&err).is_none());
+    match err {
+        UdpFramedError::TooLarge(err) => assert_eq!(err.len, 13),
+        other => panic!("expected TooLarge, got {:?}", other),
+    }
+
+    a.send((&b"abcdefghijkl"[..], b_addr)).await.unwrap();
+    match b.next().await.unwrap() {
+        Err(UdpFramedError::Truncated(err)) => assert_eq!(err.addr, a_addr),
+        other => panic!("expected Truncated, got {:?}", other),
+    }
+
+    a.send((&b"abcdefg"[..], b_addr)).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), (b"abcd".to_vec(), a_addr));
+    match b.next().await.unwrap() {
+        Err(UdpFramedError::Trailing(err)) => assert_eq!(
+            err,
+            TrailingBytes {
+                addr: a_addr,
+                len: 3,
+            }
+        ),
+        other => panic!("expected Trailing, got {:?}", other),
+    }
+
+    Ok(())
+}
+
+/// Decodes every byte of a datagram as its own frame, tagged with the sender's port.
+pub struct PerByteCodec;
+
+impl DatagramDecoder for PerByteCodec {
+    type Item = (u8, u16);
+    type Error = io::Error;
+
+    fn decode_datagram(
+        &mut self,
+        datagram: &mut BytesMut,
+        meta: &RecvMeta,
+    ) -> Result<Option<(u8, u16)>, io::Error> {
+        if datagram.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((datagram.split_to(1)[0], meta.addr.port())))
+    }
+}
+
+impl DatagramEncoder<Vec<u8>> for PerByteCodec {
+    type Error = io::Error;
+
+    fn encode_datagram(&mut self, item: Vec<u8>, dst: &mut BytesMut) -> Result<(), io::Error> {
+        dst.put_slice(&item);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn datagram_codec() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramed::new(a_soc, PerByteCodec);
+    let mut b = UdpFramedRecv::new(b_soc, PerByteCodec);
+
+    a.send((vec![1, 2], b_addr)).await?;
+    a.send((vec![], b_addr)).await?;
+    a.send((vec![3], b_addr)).await?;
+
+    let port = a_addr.port();
+    for expected in [1, 2, 3] {
+        assert_eq!(b.next().await.unwrap()?, ((expected, port), a_addr));
+    }
+
+    Ok(())
+}
+
+/// Like `PerByteCodec`, but fails on a zero byte.
+pub struct NonZeroCodec;
+
+impl DatagramDecoder for NonZeroCodec {
+    type Item = u8;
+    type Error = io::Error;
+
+    fn decode_datagram(
+        &mut self,
+        datagram: &mut BytesMut,
+        _meta: &RecvMeta,
+    ) -> Result<Option<u8>, io::Error> {
+        match datagram.first() {
+            None => Ok(None),
+            Some(0) => Err(io::Error::new(io::ErrorKind::InvalidData, "zero byte")),
+            Some(_) => Ok(Some(datagram.split_to(1)[0])),
+        }
+    }
+}
+
+impl Encoder<u8> for NonZeroCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, byte: u8, buf: &mut BytesMut) -> Result<(), io::Error> {
+        buf.put_u8(byte);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn resilient() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = UdpFramed::new(a_soc, ByteCodec);
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let cb_errors = errors.clone();
+    let mut b = UdpFramedBuilder::new()
+        .trailing_data(TrailingData::Error)
+        .build_recv(b_soc, NonZeroCodec)
+        .resilient(move |err: io::Error, addr| {
+            cb_errors.lock().unwrap().push((err.kind(), addr));
+        });
+
+    a.send((&[1, 0, 2][..], b_addr)).await?;
+    a.send((&[0][..], b_addr)).await?;
+    a.send((&[3][..], b_addr)).await?;
+
+    assert_eq!(b.next().await.unwrap()?, (1, a_addr));
+    assert_eq!(b.next().await.unwrap()?, (3, a_addr));
+    assert_eq!(
+        *errors.lock().unwrap(),
+        vec![
+            (io::ErrorKind::InvalidData, a_addr),
+            (io::ErrorKind::InvalidData, a_addr)
+        ]
+    );
+
+    // truncated datagrams are skipped as well
+    b.set_recv_max_datagram_size(2);
+    a.send((&[4, 5, 6][..], b_addr)).await?;
+    a.send((&[7][..], b_addr)).await?;
+    assert_eq!(b.next().await.unwrap()?, (7, a_addr));
+    assert_eq!(errors.lock().unwrap().len(), 3);
+
+    // the codec can also be wrapped directly, trailing bytes are skipped
+    let b = b.into_inner();
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let cb_errors = errors.clone();
+    let mut b = UdpFramedBuilder::new()
+        .trailing_data(TrailingData::Error)
+        .build_recv(
+            b,
+            Resilient::new(FixedCodec, move |err: io::Error, _| {
+                cb_errors.lock().unwrap().push(err.kind());
+            }),
+        );
+    a.send((&b"abcdef"[..], b_addr)).await?;
+    a.send((&b"ghij"[..], b_addr)).await?;
+    assert_eq!(b.next().await.unwrap()?, (b"abcd".to_vec(), a_addr));
+    assert_eq!(b.next().await.unwrap()?, (b"ghij".to_vec(), a_addr));
+    assert_eq!(*errors.lock().unwrap(), vec![io::ErrorKind::InvalidData]);
+
+    // a `DatagramEncoder` is still a `Sink` once wrapped
+    let mut a = UdpFramed::new(a.into_inner(), PerByteCodec).resilient(|_, _| {});
+    a.send((vec![1, 2, 3, 4], b_addr)).await?;
+    assert_eq!(b.next().await.unwrap()?, (vec![1, 2, 3, 4], a_addr));
+
+    Ok(())
+}
+
+/// Returns a socket that sent to a closed port, so a port unreachable error is
+/// pending on it ahead of a datagram from the port once it was reopened.
+#[cfg(target_os = "linux")]
+async fn refused_socket() -> std::io::Result<UdpSocket> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    drop(b_soc);
+    a_soc.connect(b_addr).await?;
+    a_soc.send(b"1\n").await?;
+
+    let b_soc = UdpSocket::bind(b_addr).await?;
+    b_soc.send_to(b"2\n", a_addr).await?;
+    Ok(a_soc)
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn ignore_icmp_errors() -> std::io::Result<()> {
+    let mut a = UdpFramedConnectedRecv::new(refused_socket().await?, LinesCodec::new())?;
+    let err = a.next().await.unwrap().unwrap_err();
+    assert!(
+        matches!(err, LinesCodecError::Io(ref err) if err.kind() == io::ErrorKind::ConnectionRefused)
+    );
+    assert_eq!(a.next().await.unwrap().unwrap(), "2");
+
+    let mut a = UdpFramedBuilder::new()
+        .recv_ignore_icmp_errors(true)
+        .build_connected_recv(refused_socket().await?, LinesCodec::new())?;
+    assert_eq!(a.next().await.unwrap().unwrap(), "2");
+
+    let mut a = UdpFramedRecv::new(refused_socket().await?, LinesCodec::new());
+    assert!(!a.recv_ignore_icmp_errors());
+    a.set_recv_ignore_icmp_errors(true);
+    assert_eq!(a.next().await.unwrap().unwrap().0, "2");
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn multicast() -> std::io::Result<()> {
+    let group = Ipv4Addr::new(239, 255, 0, 1);
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let a_addr = a_soc.local_addr()?;
+    let b_port = b_soc.local_addr()?.port();
+    let b_addr = (group, b_port).into();
+
+    let mut a = UdpFramed::new(a_soc, LinesCodec::new());
+    a.set_multicast_interface_v4(Ipv4Addr::LOCALHOST)?;
+    a.set_multicast_ttl_v4(1)?;
+    a.set_multicast_loop_v4(true)?;
+
+    let mut b_send = UdpFramedSend::new(b_soc.clone(), LinesCodec::new()).with_meta();
+    let mut b = UdpFramedRecv::new(b_soc, LinesCodec::new());
+    b.set_recv_pktinfo(true)?;
+    b.join_multicast_v4(group, Ipv4Addr::LOCALHOST)?;
+    let mut b = b.with_meta();
+
+    a.send(("1", b_addr)).await.unwrap();
+    let (line, meta): (String, RecvMeta) = b.next().await.unwrap().unwrap();
+    assert_eq!(line, "1");
+    assert_eq!(meta.addr, a_addr);
+    assert_eq!(meta.dst_ip, Some(group.into()));
+
+    // the reply goes out from the interface's address, not the group's
+    assert_eq!(meta.reply().src_ip, Some(Ipv4Addr::LOCALHOST.into()));
+    b_send.send(("reply", meta.reply())).await.unwrap();
+    assert_eq!(
+        a.next().await.unwrap().unwrap(),
+        ("reply".to_string(), (Ipv4Addr::LOCALHOST, b_port).into())
+    );
+
+    b.get_ref().leave_multicast_v4(group, Ipv4Addr::LOCALHOST)?;
+    b.get_ref()
+        .join_source_multicast_v4(group, Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST)?;
+    a.send(("2", b_addr)).await.unwrap();
+    let (line, meta) = b.next().await.unwrap().unwrap();
+    assert_eq!(line, "2");
+    assert_eq!(meta.dst_ip, Some(group.into()));
+    b.get_ref()
+        .leave_source_multicast_v4(group, Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST)?;
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn discovery() -> std::io::Result<()> {
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("0.0.0.0:0").await?;
+    let c_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let b_addr = b_soc.local_addr()?;
+    let c_addr = c_soc.local_addr()?;
+    let target = ([127, 255, 255, 255], b_addr.port()).into();
+
+    // answers every probe itself and from `c_soc`, so there are two responders
+    let responder = tokio::spawn(async move {
+        let mut probes = 0;
+        let mut buf = [0; 64];
+        while let Ok(Ok((len, addr))) = tokio::time::timeout(
+            std::time::Duration::from_millis(500),
+            b_soc.recv_from(&mut buf),
+        )
+        .await
+        {
+            assert_eq!(&buf[..len], b"PROBE\n");
+            probes += 1;
+            b_soc.send_to(b"b\n", addr).await.unwrap();
+            c_soc.send_to(b"c\n", addr).await.unwrap();
+        }
+        probes
+    });
+
+    let mut a = Discovery::new(a_soc, LinesCodec::new(), "PROBE", target)?;
+    assert!(a.get_ref().broadcast()?);
+    a.set_probe_interval(std::time::Duration::from_millis(20));
+    a.set_window(std::time::Duration::from_millis(200));
+
+    let mut found = Vec::new();
+    while let Some(item) = a.next().await {
+        found.push(item.unwrap());
+    }
+    found.sort();
+    let b_addr = ([127, 0, 0, 1], b_addr.port()).into();
+    assert_eq!(
+        found,
+        [("b".to_string(), b_addr), ("c".to_string(), c_addr)]
+    );
+    assert_eq!(a.responders().len(), 2);
+    assert!(responder.await.unwrap() > 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[should_panic(expected = "probe interval must be non-zero")]
+async fn discovery_zero_probe_interval() {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let target = "127.0.0.1:9".parse().unwrap();
+    let mut discovery = Discovery::new(socket, LinesCodec::new(), "probe", target).unwrap();
+    discovery.set_probe_interval(std::time::Duration::ZERO);
+}
+
+#[tokio::test]
+async fn mux() -> std::io::Result<()> {
+    let server = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let server_addr = server.local_addr()?;
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut mux = UdpFramedMux::new(server, LinesCodec::new());
+    mux.set_idle_timeout(Some(std::time::Duration::from_millis(100)));
+    let mut a = UdpFramed::new(a_soc, LinesCodec::new());
+    let mut b = UdpFramed::new(b_soc, LinesCodec::new());
+
+    a.send(("a1", server_addr)).await.unwrap();
+    let (mut a_stream, mut a_sink) = mux.next().await.unwrap().unwrap();
+    assert_eq!(a_stream.peer_addr(), a_addr);
+    assert_eq!(a_sink.peer_addr(), a_addr);
+
+    a.send(("a2", server_addr)).await.unwrap();
+    b.send(("b1", server_addr)).await.unwrap();
+    let (mut b_stream, mut b_sink) = mux.next().await.unwrap().unwrap();
+    assert_eq!(b_stream.peer_addr(), b_addr);
+    assert_eq!(mux.peers(), 2);
+
+    assert_eq!(a_stream.next().await.unwrap(), "a1");
+    assert_eq!(a_stream.next().await.unwrap(), "a2");
+    assert_eq!(b_stream.next().await.unwrap(), "b1");
+
+    a_sink.send("to a").await.unwrap();
+    b_sink.send("to b").await.unwrap();
+    assert_eq!(
+        a.next().await.unwrap().unwrap(),
+        ("to a".to_string(), server_addr)
+    );
+    assert_eq!(
+        b.next().await.unwrap().unwrap(),
+        ("to b".to_string(), server_addr)
+    );
+
+    // both peers go quiet and are evicted while the mux is polled
+    tokio::select! {
+        end = a_stream.next() => assert!(end.is_none()),
+        _ = mux.next() => panic!("no new peers expected"),
+    }
+    assert!(b_stream.next().await.is_none());
+    assert_eq!(mux.peers(), 0);
+
+    // an evicted peer is accepted again
+    a.send(("a3", server_addr)).await.unwrap();
+    let (mut a_stream, _) = mux.next().await.unwrap().unwrap();
+    assert_eq!(a_stream.next().await.unwrap(), "a3");
+
+    // a zero timeout is raised to the timer's resolution
+    mux.set_idle_timeout(Some(std::time::Duration::ZERO));
+    assert_eq!(
+        mux.idle_timeout(),
+        Some(std::time::Duration::from_millis(1))
+    );
+    assert!(mux.next().now_or_never().is_none());
+
+    Ok(())
+}
+
+/// Frames a request or response as its id and payload separated by a space.
+#[derive(Clone, Default)]
+pub struct IdCodec {
+    next_id: u32,
+}
+
+impl Decoder for IdCodec {
+    type Item = (u32, String);
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<(u32, String)>, io::Error> {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let msg = String::from_utf8(buf.split().to_vec())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let (id, payload) = msg
+            .split_once(' ')
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing id"))?;
+        let id = id
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Some((id, payload.to_string())))
+    }
+}
+
+impl Encoder<(u32, String)> for IdCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, (id, payload): (u32, String), buf: &mut BytesMut) -> io::Result<()> {
+        buf.put_slice(format!("{} {}", id, payload).as_bytes());
+        Ok(())
+    }
+}
+
+impl Correlate for IdCodec {
+    type Request = (u32, String);
+    type Id = u32;
+
+    // keeps an id the request already has
+    fn assign_id(&mut self, request: &mut (u32, String)) -> u32 {
+        if request.0 == 0 {
+            self.next_id += 1;
+            request.0 = self.next_id;
+        }
+        request.0
+    }
+
+    fn response_id(&self, response: &(u32, String)) -> Option<u32> {
+        Some(response.0)
+    }
+}
+
+#[tokio::test]
+async fn client() -> std::io::Result<()> {
+    use std::time::Duration;
+
+    let a_soc = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let c_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let b_addr = b_soc.local_addr()?;
+    let c_addr = c_soc.local_addr()?;
+
+    let mut client = UdpClient::new(a_soc, IdCodec::default());
+    client.set_timeout(Duration::from_millis(50));
+    client.set_retries(1);
+
+    // b ignores the first copy of every request, answers the retransmission, and
+    // sends a stray response with an unknown id first
+    tokio::spawn(async move {
+        let mut b = UdpFramed::new(b_soc, IdCodec::default());
+        let mut seen = std::collections::HashSet::new();
+        while let Some(Ok(((id, payload), addr))) = b.next().await {
+            if seen.insert(id) {
+                continue;
+            }
+            b.send(((id + 100, "stray".to_string()), addr))
+                .await
+                .unwrap();
+            b.send(((id, payload.to_uppercase()), addr)).await.unwrap();
+        }
+    });
+
+    let (first, second) = futures::future::join(
+        client.request((0, "abc".to_string()), b_addr),
+        client.request((0, "def".to_string()), b_addr),
+    )
+    .await;
+    let mut responses = vec![first?.1, second?.1];
+    responses.sort();
+    assert_eq!(responses, vec!["ABC".to_string(), "DEF".to_string()]);
+    assert_eq!(client.pending(), 0);
+
+    // c never answers
+    let err = client
+        .request((0, "ghi".to_string()), c_addr)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(client.pending(), 0);
+
+    let started = tokio::time::Instant::now();
+    let err = client
+        .request_with_timeout((0, "jkl".to_string()), c_addr, Duration::from_millis(5))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(started.elapsed() < Duration::from_millis(100));
+
+    // an id still in use by another request is refused without disturbing it
+    let waiting = client.request((7, "mno".to_string()), c_addr);
+    tokio::pin!(waiting);
+    assert!((&mut waiting).now_or_never().is_none());
+    let err = client
+        .request((7, "pqr".to_string()), c_addr)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(client.pending(), 1);
+    assert_eq!(waiting.await.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    assert_eq!(client.pending(), 0);
+    drop(c_soc);
+
+    // d answers every copy
+    let d_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let d_addr = d_soc.local_addr()?;
+    tokio::spawn(async move {
+        let mut d = UdpFramed::new(d_soc, IdCodec::default());
+        while let Some(Ok(((id, payload), addr))) = d.next().await {
+            d.send(((id, payload.to_uppercase()), addr)).await.unwrap();
+        }
+    });
+
+    // an id reused once its response arrived, before the first request finished,
+    // stays with the new request
+    let first = client.request((9, "stu".to_string()), d_addr);
+    tokio::pin!(first);
+    assert!((&mut first).now_or_never().is_none());
+    while client.pending() > 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let e_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut second = Box::pin(client.request((9, "vwx".to_string()), e_soc.local_addr()?));
+    assert!((&mut second).now_or_never().is_none());
+    assert_eq!(first.await?.1, "STU");
+    assert_eq!(client.pending(), 1);
+    drop(second);
+    assert_eq!(client.pending(), 0);
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn server() -> std::io::Result<()> {
+    use std::time::Duration;
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_udp_framed::UdpServer;
+
+    let a_soc = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+
+    let (started_tx, mut started) = mpsc::unbounded_channel();
+    let mut server = UdpServer::new(
+        a_soc,
+        LinesCodec::new(),
+        move |line: String, meta: RecvMeta| {
+            let started_tx = started_tx.clone();
+            async move {
+                started_tx.send(meta.addr).unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                if line == "ignore" {
+                    return None;
+                }
+                Some(line.to_uppercase())
+            }
+        },
+    );
+    server.set_concurrency_limit(2);
+    assert_eq!(server.concurrency_limit(), 2);
+
+    let (stop_tx, stop) = oneshot::channel::<()>();
+    let serving = tokio::spawn(server.serve_with_shutdown(async {
+        stop.await.ok();
+    }));
+
+    let mut b = UdpFramed::new(b_soc, LinesCodec::new());
+    for line in ["ignore", "abc", "def"] {
+        b.send((line, a_addr)).await.unwrap();
+    }
+    let mut responses = vec![
+        b.next().await.unwrap().unwrap().0,
+        b.next().await.unwrap().unwrap().0,
+    ];
+    responses.sort();
+    assert_eq!(responses, vec!["ABC".to_string(), "DEF".to_string()]);
+
+    // shutting down waits for the handler already running and sends its response
+    b.send(("slow", a_addr)).await.unwrap();
+    for _ in 0..4 {
+        started.recv().await.unwrap();
+    }
+    stop_tx.send(()).unwrap();
+    assert_eq!(
+        b.next().await.unwrap().unwrap(),
+        ("SLOW".to_string(), a_addr)
+    );
+    serving.await.unwrap().unwrap();
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn server_send_error() -> std::io::Result<()> {
+    use tokio::sync::mpsc;
+    use tokio_udp_framed::UdpServer;
+
+    let a_soc = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let recv = UdpFramedRecv::new(a_soc.clone(), LinesCodec::new());
+    let mut send = UdpFramedSend::new(a_soc, LinesCodec::new());
+    send.set_send_max_datagram_size(8);
+    let mut server = UdpServer::from_parts(recv, send, |line: String, _: RecvMeta| async move {
+        Some(line.repeat(4))
+    });
+    let (failed_tx, mut failed) = mpsc::unbounded_channel();
+    server.on_send_error(move |err, addr| failed_tx.send((err, addr)).unwrap());
+    let serving = tokio::spawn(server.serve());
+
+    // a response too large to send is reported and the server keeps serving
+    let mut b = UdpFramed::new(b_soc, LinesCodec::new());
+    b.send(("abc", a_addr)).await.unwrap();
+    let (err, addr) = failed.recv().await.unwrap();
+    assert_eq!(addr, b_addr);
+    match err {
+        LinesCodecError::Io(err) => assert!(err.get_ref().unwrap().is::<DatagramTooLarge>()),
+        err => panic!("unexpected error {:?}", err),
+    }
+
+    b.send(("a", a_addr)).await.unwrap();
+    assert_eq!(
+        b.next().await.unwrap().unwrap(),
+        ("aaaa".to_string(), a_addr)
+    );
+
+    serving.abort();
+    Ok(())
+}
+
+#[cfg(feature = "reliable")]
+#[tokio::test]
+async fn reliable() -> std::io::Result<()> {
+    use std::time::Duration;
+    use tokio_udp_framed::Reliable;
+
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let relay_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+    let relay_addr = relay_soc.local_addr()?;
+
+    // forwards between `a` and `b`, dropping some datagrams in each direction and
+    // holding others back until the next one has been forwarded
+    let relay = tokio::spawn(async move {
+        let mut counts = [0; 2];
+        let mut held: [Option<Vec<u8>>; 2] = [None, None];
+        let mut buf = [0; 1024];
+        loop {
+            let (len, from) = relay_soc.recv_from(&mut buf).await.unwrap();
+            let (dir, to) = if from == a_addr {
+                (0, b_addr)
+            } else {
+                (1, a_addr)
+            };
+            counts[dir] += 1;
+            match counts[dir] % 7 {
+                3 => continue,
+                5 if held[dir].is_none() => held[dir] = Some(buf[..len].to_vec()),
+                _ => {
+                    relay_soc.send_to(&buf[..len], to).await.unwrap();
+                    if let Some(held) = held[dir].take() {
+                        relay_soc.send_to(&held, to).await.unwrap();
+                    }
+                }
+            }
+        }
+    });
+
+    let mut a = Reliable::new(a_soc, LinesCodec::new(), relay_addr);
+    let mut b = Reliable::new(b_soc, LinesCodec::new(), relay_addr);
+    a.set_retransmit_timeout(Duration::from_millis(20));
+    b.set_retransmit_timeout(Duration::from_millis(20));
+    a.set_window(8);
+
+    let (lines_tx, lines_rx) = tokio::sync::oneshot::channel();
+    let b_task = tokio::spawn(async move {
+        let mut lines = Vec::new();
+        while lines.len() < 100 {
+            lines.push(b.next().await.unwrap().unwrap());
+        }
+        lines_tx.send(lines).unwrap();
+        b.send("done").await.unwrap();
+        // keep acknowledging retransmissions
+        while b.next().await.is_some() {}
+    });
+
+    for i in 0..100 {
+        a.feed(i.to_string()).await.unwrap();
+        assert!(a.unacked() <= 8);
+    }
+    SinkExt::<String>::flush(&mut a).await.unwrap();
+    assert_eq!(a.unacked(), 0);
+    assert_eq!(a.next().await.unwrap().unwrap(), "done");
+
+    let expected: Vec<String> = (0..100).map(|i: i32| i.to_string()).collect();
+    assert_eq!(lines_rx.await.unwrap(), expected);
+
+    b_task.abort();
+    relay.abort();
+
+    Ok(())
+}
+
+#[cfg(feature = "reliable")]
+#[tokio::test]
+async fn reliable_codec_error() -> std::io::Result<()> {
+    use std::time::Duration;
+    use tokio_udp_framed::Reliable;
+
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let a_addr = a_soc.local_addr()?;
+    let b_addr = b_soc.local_addr()?;
+
+    let mut a = Reliable::new(a_soc, NonZeroCodec, b_addr);
+    a.set_retransmit_timeout(Duration::from_secs(10));
+    a.feed(7).await?;
+    // flushing waits for an acknowledgement `b` doesn't send yet
+    let flush = tokio::time::timeout(Duration::from_millis(50), SinkExt::<u8>::flush(&mut a));
+    assert!(flush.await.is_err());
+
+    let mut buf = [0; 64];
+    let (len, _) = b_soc.recv_from(&mut buf).await?;
+    assert_eq!(&buf[..len], &[0, 0, 0, 0, 0, 0, 0, 0, 0, 7]);
+
+    // frame 0 fails to decode, and what follows it looks like an acknowledgement
+    let mut packet = BytesMut::new();
+    packet.put_u8(0);
+    packet.put_u64(0);
+    packet.put_u8(0);
+    packet.put_u8(1);
+    packet.put_u64(1);
+    packet.put_u64(0);
+    b_soc.send_to(&packet, a_addr).await?;
+    let err = a.next().await.unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(a.unacked(), 1);
+
+    // the frame wasn't acknowledged, so it can be sent again
+    b_soc
+        .send_to(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 5], a_addr)
+        .await?;
+    assert_eq!(a.next().await.unwrap()?, 5);
+    let (len, _) = b_soc.recv_from(&mut buf).await?;
+    assert_eq!(
+        &buf[..len],
+        &[1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+
+    b_soc.send_to(&buf[..len], a_addr).await?;
+    SinkExt::<u8>::flush(&mut a).await?;
+    assert_eq!(a.unacked(), 0);
+
+    Ok(())
+}
+
+#[cfg(feature = "reliable")]
+#[tokio::test]
+async fn reliable_gives_up() -> std::io::Result<()> {
+    use std::time::Duration;
+    use tokio_udp_framed::Reliable;
+
+    let a_soc = UdpSocket::bind("127.0.0.1:0").await?;
+    let b_soc = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let mut a = Reliable::new(a_soc, LinesCodec::new(), b_soc.local_addr()?);
+    a.set_retransmit_timeout(Duration::from_millis(10));
+    a.set_max_retransmits(2);
+
+    let err = a.send("1").await.unwrap_err();
+    assert!(matches!(err, LinesCodecError::Io(ref err) if err.kind() == io::ErrorKind::TimedOut));
+    assert_eq!(a.unacked(), 0);
+    // the error is only returned once, then the stream ends
+    assert!(a.next().await.is_none());
+    assert!(a.send("2").await.is_err());
+
+    let mut buf = [0; 64];
+    for _ in 0..3 {
+        b_soc.recv_from(&mut buf).await?;
+    }
+
+    Ok(())
+}